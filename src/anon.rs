@@ -1,10 +1,14 @@
-use crate::{MMap, MMapMut, MSyncType, MMapConfig, base::MMapBase, MAdviseConfig, AddrHint};
+use crate::{MMap, MMapMut, MMapResize, MSyncType, MMapConfig, base::MMapBase, MAdviseConfig, AddrHint};
 use std::ops::{Deref, DerefMut};
 
 pub struct AnonMMap {
     inner: MMapBase,
 }
 
+// SAFETY: see the thread safety notes on `MMap`.
+unsafe impl Send for AnonMMap {}
+unsafe impl Sync for AnonMMap {}
+
 impl AnonMMap {
     pub fn new(addr_hint: AddrHint, map_len: usize, conf: MMapConfig) -> std::io::Result<Self> {
         let flags = conf.value() | libc::MAP_ANON;
@@ -43,7 +47,7 @@ impl MMap for AnonMMap {
 impl TryFrom<AnonMMapMut> for AnonMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMapMut) -> std::io::Result<Self> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -52,7 +56,7 @@ impl TryFrom<AnonMMapMut> for AnonMMap {
 impl TryFrom<AnonExecutableMMap> for AnonMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMap) -> std::io::Result<Self> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -61,7 +65,7 @@ impl TryFrom<AnonExecutableMMap> for AnonMMap {
 impl TryFrom<AnonExecutableMMapMut> for AnonMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMapMut) -> std::io::Result<Self> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -74,6 +78,9 @@ pub struct AnonMMapMut {
     inner: MMapBase,
 }
 
+// SAFETY: see the thread safety notes on `MMapMut`.
+unsafe impl Send for AnonMMapMut {}
+
 impl AnonMMapMut {
     pub fn new(addr_hint: AddrHint, map_len: usize, conf: MMapConfig) -> std::io::Result<Self> {
         let flags = conf.value() | libc::MAP_ANON;
@@ -122,10 +129,17 @@ impl MMapMut for AnonMMapMut {
     }
 }
 
+impl MMapResize for AnonMMapMut {
+    /// Only supported on Linux. New pages are zeroed.
+    fn resize(&mut self, new_len: usize) -> std::io::Result<()> {
+        self.inner.remap(new_len)
+    }
+}
+
 impl TryFrom<AnonMMap> for AnonMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -134,7 +148,7 @@ impl TryFrom<AnonMMap> for AnonMMapMut {
 impl TryFrom<AnonExecutableMMap> for AnonMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -143,7 +157,7 @@ impl TryFrom<AnonExecutableMMap> for AnonMMapMut {
 impl TryFrom<AnonExecutableMMapMut> for AnonMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -154,6 +168,10 @@ pub struct AnonExecutableMMap {
     inner: MMapBase,
 }
 
+// SAFETY: see the thread safety notes on `MMap`.
+unsafe impl Send for AnonExecutableMMap {}
+unsafe impl Sync for AnonExecutableMMap {}
+
 impl AnonExecutableMMap {
     pub fn new(addr_hint: AddrHint, map_len: usize, conf: MMapConfig) -> std::io::Result<Self> {
         let flags = conf.value() | libc::MAP_ANON;
@@ -193,7 +211,7 @@ impl MMap for AnonExecutableMMap {
 impl TryFrom<AnonExecutableMMapMut> for AnonExecutableMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMapMut) -> std::io::Result<Self> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -202,7 +220,7 @@ impl TryFrom<AnonExecutableMMapMut> for AnonExecutableMMap {
 impl TryFrom<AnonMMap> for AnonExecutableMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -211,7 +229,7 @@ impl TryFrom<AnonMMap> for AnonExecutableMMap {
 impl TryFrom<AnonMMapMut> for AnonExecutableMMap {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -222,6 +240,9 @@ pub struct AnonExecutableMMapMut {
     inner: MMapBase,
 }
 
+// SAFETY: see the thread safety notes on `MMapMut`.
+unsafe impl Send for AnonExecutableMMapMut {}
+
 impl AnonExecutableMMapMut {
     pub fn new(addr_hint: AddrHint, map_len: usize, conf: MMapConfig) -> std::io::Result<Self> {
         let flags = conf.value() | libc::MAP_ANON;
@@ -273,7 +294,7 @@ impl MMapMut for AnonExecutableMMapMut {
 impl TryFrom<AnonExecutableMMap> for AnonExecutableMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonExecutableMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -282,7 +303,7 @@ impl TryFrom<AnonExecutableMMap> for AnonExecutableMMapMut {
 impl TryFrom<AnonMMap> for AnonExecutableMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -291,7 +312,7 @@ impl TryFrom<AnonMMap> for AnonExecutableMMapMut {
 impl TryFrom<AnonMMapMut> for AnonExecutableMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: AnonMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
         Ok(Self { inner })
     }
@@ -304,7 +325,7 @@ mod tests {
     #[inline]
     fn make(config: MMapConfig) {
         let r = AnonMMap::new(AddrHint::None, 12000, config);
-        assert_eq!(r.is_ok(), true);
+        assert!(r.is_ok());
     }
 
     #[test]
@@ -348,4 +369,46 @@ mod tests {
         r.copy_from_slice(&m);
         assert_eq!(r, w);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn zero_length_mappings() {
+        let m = AnonMMap::new(AddrHint::None, 0, MMapConfig::new().map_private()).unwrap();
+        assert!(m.is_empty());
+        m.sync(MSyncType::Sync).unwrap();
+        m.advise(MAdviseConfig::new().madv_willneed()).unwrap();
+        m.sync_range(0..0, MSyncType::Async).unwrap();
+        m.populate_read(0..0).unwrap();
+        let m: AnonMMapMut = m.try_into().unwrap();
+        let m: AnonMMap = m.try_into().unwrap();
+        m.unmap().unwrap();
+
+        let m = AnonMMapMut::new(AddrHint::None, 0, MMapConfig::new().map_private()).unwrap();
+        let m = crate::SharedMMapMut::new(m);
+        assert_eq!(crate::ByteSink::write_at(&m, &[1], 0).unwrap(), 0);
+        let mut m = m.into_inner();
+        #[cfg(target_os = "linux")]
+        {
+            m.resize(100).unwrap();
+            m[99] = 1;
+            m.resize(0).unwrap();
+            assert!(m.is_empty());
+            m.resize(10).unwrap();
+            assert_eq!(m[..], [0; 10]);
+        }
+        m.unmap().unwrap();
+    }
+
+    #[test]
+    fn advise_range_on_anon_memory() {
+        let ps = crate::base::get_page_size() as usize;
+        let m = AnonMMapMut::new(AddrHint::None, 4 * ps, MMapConfig::new().map_private()).unwrap();
+        let e = m.advise_range(0..ps, MAdviseConfig::new().madv_dontneed()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        m.advise_range(ps..2 * ps, MAdviseConfig::new().madv_willneed()).unwrap();
+        #[cfg(target_os = "linux")]
+        {
+            m.advise_range(0..2 * ps, MAdviseConfig::new().madv_populate_read()).unwrap();
+            m.advise_range(2 * ps..4 * ps, MAdviseConfig::new().madv_populate_write()).unwrap();
+        }
+    }
+}
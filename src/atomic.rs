@@ -0,0 +1,215 @@
+use crate::{AddrHint, FileMMapMut, MAdviseConfig, MMap, MMapConfig, MMapMut, MSyncType};
+use std::fs::{File, OpenOptions};
+use std::ops::{Deref, DerefMut, Range};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::prelude::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Path of the temporary file, which is removed on drop unless it was
+/// renamed over the destination.
+struct TempPath {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Replaces a file atomically and durably with contents written through a
+/// mapping.
+///
+/// The contents are written to a temporary file next to the destination,
+/// which is accessed like any other `MMapMut`. `commit` flushes the
+/// mapping and the file, renames it over the destination and flushes the
+/// directory, so after a crash the destination holds either the old or the
+/// complete new contents. Dropping (or unmapping) without `commit` removes
+/// the temporary file and leaves the destination untouched.
+pub struct AtomicMappedWrite {
+    mmap: FileMMapMut,
+    tmp: TempPath,
+    dest: PathBuf,
+}
+
+impl AtomicMappedWrite {
+    /// Create a temporary file of `len` bytes next to `path` and map it.
+    /// If the destination exists the temporary file gets its permissions
+    /// and, where allowed, its owner and group.
+    pub fn new<P: AsRef<Path>>(path: P, len: u64) -> std::io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dest = path.as_ref().to_path_buf();
+        let name = dest.file_name().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "destination has no file name")
+        })?;
+        let (tmp, file) = loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let mut tmp_name = std::ffi::OsString::from(".");
+            tmp_name.push(name);
+            tmp_name.push(format!(".{}-{}.tmp", std::process::id(), n));
+            let tmp = dest.with_file_name(tmp_name);
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&tmp) {
+                Ok(file) => break (TempPath { path: tmp, keep: false }, file),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        match std::fs::metadata(&dest) {
+            Ok(meta) => {
+                file.set_permissions(meta.permissions())?;
+                // changing the owner requires privileges, keep ours otherwise.
+                unsafe { libc::fchown(file.as_raw_fd(), meta.uid(), meta.gid()) };
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        crate::filemap::preallocate(&file, len)?;
+        let mmap = FileMMapMut::new_owned(AddrHint::None, MMapConfig::new().map_shared(), file, 0)?;
+        Ok(Self { mmap, tmp, dest })
+    }
+
+    /// Return the path of the destination.
+    pub fn path(&self) -> &Path {
+        &self.dest
+    }
+
+    /// Flush the contents, rename the temporary file over the destination
+    /// and flush the directory.
+    pub fn commit(mut self) -> std::io::Result<()> {
+        // flushes the file as well, the mapping owns it.
+        self.mmap.sync(MSyncType::Sync)?;
+        std::fs::rename(&self.tmp.path, &self.dest)?;
+        self.tmp.keep = true;
+        let dir = match self.dest.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+
+    /// Remove the temporary file, the destination is left untouched.
+    pub fn abort(mut self) -> std::io::Result<()> {
+        self.tmp.keep = true;
+        std::fs::remove_file(&self.tmp.path)
+    }
+}
+
+impl Deref for AtomicMappedWrite {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.mmap
+    }
+}
+
+impl DerefMut for AtomicMappedWrite {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.mmap
+    }
+}
+
+impl MMap for AtomicMappedWrite {
+    fn as_ptr(&self) -> *const u8 {
+        self.mmap.as_ptr()
+    }
+    fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
+        self.mmap.advise(config)
+    }
+    fn is_shared_file(&self) -> bool {
+        self.mmap.is_shared_file()
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        self.mmap.populate_read(range)
+    }
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.mmap.try_read(range, f)
+    }
+    fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
+        self.mmap.sync(typ)
+    }
+    /// Unmap without `commit`, the temporary file is removed.
+    fn unmap(self) -> std::io::Result<()> {
+        self.mmap.unmap()
+    }
+}
+
+impl MMapMut for AtomicMappedWrite {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.mmap.as_mut_ptr()
+    }
+    fn populate_write(&mut self, range: Range<usize>) -> std::io::Result<()> {
+        self.mmap.populate_write(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestFile;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Count the temporary files left next to `path`.
+    fn leftovers(path: &Path) -> usize {
+        let prefix = format!(".{}.", path.file_name().unwrap().to_str().unwrap());
+        std::fs::read_dir(path.parent().unwrap()).unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)))
+            .count()
+    }
+
+    #[test]
+    fn commit_replaces_destination() {
+        let tf = TestFile::new(b"old contents");
+        let mut w = AtomicMappedWrite::new(&tf.path, 16).unwrap();
+        w.populate_write(0..16).unwrap();
+        w.copy_from_slice(b"new contents....");
+        w.sync_range(0..4, MSyncType::Async).unwrap();
+        assert_eq!(std::fs::read(&tf.path).unwrap(), b"old contents");
+        assert_eq!(leftovers(&tf.path), 1);
+        w.commit().unwrap();
+        assert_eq!(std::fs::read(&tf.path).unwrap(), b"new contents....");
+        assert_eq!(leftovers(&tf.path), 0);
+    }
+
+    #[test]
+    fn drop_and_abort_keep_destination() {
+        let tf = TestFile::new(b"old");
+        let mut w = AtomicMappedWrite::new(&tf.path, 3).unwrap();
+        w.copy_from_slice(b"new");
+        drop(w);
+        let w = AtomicMappedWrite::new(&tf.path, 3).unwrap();
+        w.abort().unwrap();
+        assert_eq!(std::fs::read(&tf.path).unwrap(), b"old");
+        assert_eq!(leftovers(&tf.path), 0);
+
+        // the destination does not need to exist.
+        let tf = TestFile::new(b"");
+        std::fs::remove_file(&tf.path).unwrap();
+        let mut w = AtomicMappedWrite::new(&tf.path, 2).unwrap();
+        assert_eq!(w.path(), tf.path);
+        w.copy_from_slice(b"ok");
+        w.commit().unwrap();
+        assert_eq!(std::fs::read(&tf.path).unwrap(), b"ok");
+    }
+
+    #[test]
+    fn permissions_of_destination_are_kept() {
+        let tf = TestFile::new(b"old");
+        std::fs::set_permissions(&tf.path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let mut w = AtomicMappedWrite::new(&tf.path, 3).unwrap();
+        w.copy_from_slice(b"new");
+        w.commit().unwrap();
+        let meta = std::fs::metadata(&tf.path).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        assert_eq!(std::fs::read(&tf.path).unwrap(), b"new");
+
+        // unmapping without commit removes the temporary file.
+        let w = AtomicMappedWrite::new(&tf.path, 3).unwrap();
+        assert_eq!(leftovers(&tf.path), 1);
+        w.unmap().unwrap();
+        assert_eq!(leftovers(&tf.path), 0);
+    }
+}
@@ -1,6 +1,9 @@
 use std::ops::{Deref, DerefMut};
+use std::fs::File;
+use std::sync::{Arc, RwLock};
 use libc::{mmap, munmap, mprotect, msync, madvise};
-use crate::{MSyncType, MMap, MMapMut, MMapExec, MMapExecMut, MAdviseConfig};
+use crate::{MSyncType, MMap, MMapMut, MMapExec, MMapExecMut, MAdviseConfig, DropPolicy};
+use crate::error::{errno_of, last_errno, MMapError};
 
 /// Util function to determine the page size on unix operating systems.
 #[cfg(unix)]
@@ -13,25 +16,202 @@ pub(crate) fn get_page_size() -> i64 {
 /// Util function to check if a given address is aligned on the page boundary.
 pub(crate) fn ptr_is_page_aligned<T>(addr: *const T) -> bool {
     let ps = get_page_size();
-    addr as u64 % ps as u64 == 0
+    (addr as u64).is_multiple_of(ps as u64)
 }
 
+/// Translate `range` of a mapping starting at `base` with length `len` into
+/// an address and length that can be passed to syscalls which require page 
+/// aligned addresses. The start is rounded down to the page boundary.
+pub(crate) fn page_range(base: *const u8, len: usize, range: std::ops::Range<usize>) -> std::io::Result<(*mut u8, usize)> {
+    if range.start > range.end || range.end > len {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of the mapped region"));
+    }
+    if range.is_empty() {
+        return Ok((base as *mut u8, 0));
+    }
+    let ps = get_page_size() as usize;
+    let start = base as usize + range.start;
+    let aligned = start - start % ps;
+    let plen = base as usize + range.end - aligned;
+    Ok((aligned as *mut u8, plen))
+}
+
+/// Wraps the msync syscall for an arbitrary page aligned region.
+pub(crate) fn msync_region(addr: *mut u8, len: usize, typ: &MSyncType) -> std::io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    unsafe {
+        let flags = typ.as_flag();
+        let rc = msync(addr as *mut libc::c_void, len, flags);
+        if rc != 0 {
+            return Err(MMapError::Msync { errno: last_errno(), addr: addr as usize, len, flags }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Wraps the madvise syscall for an arbitrary page aligned region.
+pub(crate) fn madvise_region(addr: *mut u8, len: usize, flag: i32) -> std::io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    unsafe {
+        let rc = madvise(addr as *mut libc::c_void, len, flag);
+        if rc != 0 {
+            return Err(MMapError::Madvise { errno: last_errno(), addr: addr as usize, len, advice: flag }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Fail for advice that discards the pages of a mapping which is not a
+/// shared file mapping. Its pages would read as zeros afterwards, i.e. 
+/// memory that may be borrowed as `&[u8]` would change.
+pub(crate) fn check_advice(config: &MAdviseConfig, shared_file: bool) -> std::io::Result<()> {
+    if config.discards_pages() && !shared_file {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "advice would discard the contents of a private or anonymous mapping"));
+    }
+    Ok(())
+}
+
+/// Part of a region that `touch_region` may access.
+pub(crate) enum Extent {
+    /// Anonymous memory, all of it.
+    All,
+    /// The first bytes, which are backed by the mapped file.
+    File(usize),
+    /// A file mapping whose file length cannot be checked, e.g. because
+    /// the file may have been closed.
+    Unknown,
+}
+
+/// Fault in all pages of a page aligned region, for writing if `write` is
+/// set. Uses MADV_POPULATE_READ/WRITE where the kernel supports it and
+/// falls back to `touch_region` with the extent returned by `extent`
+/// otherwise.
+///
+/// The madvise calls fail with EFAULT if the region extends beyond the end
+/// of the mapped file, which is reported as UnexpectedEof.
+pub(crate) fn populate_region(addr: *mut u8, len: usize, write: bool, extent: impl FnOnce() -> std::io::Result<Extent>) -> std::io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let flag = if write { libc::MADV_POPULATE_WRITE } else { libc::MADV_POPULATE_READ };
+        match madvise_region(addr, len, flag) {
+            Ok(()) => return Ok(()),
+            // kernels before 5.14 do not know the advice.
+            Err(e) if errno_of(&e) == Some(libc::EINVAL) => {}
+            Err(e) if errno_of(&e) == Some(libc::EFAULT) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "mapped file is shorter than the populated range"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    touch_region(addr, len, write, extent()?)
+}
+
+/// Fault in the pages of a page aligned region by touching them one by one.
+///
+/// Touching a page beyond the end of the mapped file raises SIGBUS, so only
+/// the pages within `extent` are touched and UnexpectedEof is returned if
+/// the region extends beyond it. With `Extent::Unknown` nothing is touched
+/// and Unsupported is returned.
+pub(crate) fn touch_region(addr: *mut u8, len: usize, write: bool, extent: Extent) -> std::io::Result<()> {
+    let ps = get_page_size() as usize;
+    let touch = |len: usize| {
+        for off in (0..len).step_by(ps) {
+            unsafe {
+                let p = addr.add(off);
+                let v = std::ptr::read_volatile(p);
+                if write {
+                    std::ptr::write_volatile(p, v);
+                }
+            }
+        }
+    };
+    match extent {
+        Extent::All => {
+            touch(len);
+            Ok(())
+        }
+        Extent::File(backed) => {
+            // the rest of the last page of the file reads as zeros.
+            let backed = backed.checked_next_multiple_of(ps).unwrap_or(usize::MAX);
+            touch(backed.min(len));
+            if backed < len {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "mapped file is shorter than the populated range"));
+            }
+            Ok(())
+        }
+        Extent::Unknown => {
+            Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "cannot populate a mapping of a file it does not own without MADV_POPULATE"))
+        }
+    }
+}
+
+type DropErrorHook = Arc<dyn Fn(&std::io::Error) + Send + Sync>;
+
+static DROP_ERROR_HOOK: RwLock<Option<DropErrorHook>> = RwLock::new(None);
+
+/// Set the function that receives the errors of mappings being dropped,
+/// i.e. failed flushes of the `DropPolicy` and failed munmap calls, e.g. to
+/// log them. Replaces the previous hook. Without a hook the errors are 
+/// ignored. The hook runs on the dropping thread and must not panic, it
+/// may set or clear the hook.
+pub fn set_drop_error_hook<F: Fn(&std::io::Error) + Send + Sync + 'static>(hook: F) {
+    *DROP_ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+}
+
+/// Remove the hook set with `set_drop_error_hook`.
+pub fn clear_drop_error_hook() {
+    *DROP_ERROR_HOOK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+fn report_drop_error(err: std::io::Error) {
+    // the hook is called without holding the lock.
+    let hook = DROP_ERROR_HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        hook(&err);
+    }
+}
+
+/// Mapped region. A region of length zero is not mapped at all (mmap 
+/// rejects it with EINVAL), it behaves like an empty slice and all 
+/// operations on it succeed without doing anything.
 pub(crate) struct MMapBase {
     map_len: usize,
     map_ptr: *mut u8,
+    /// Arguments of the mmap call, used to map an empty region when it is
+    /// grown with `remap`.
+    prot: i32,
+    flags: i32,
+    fd: i32,
+    map_off: i64,
+    drop_policy: DropPolicy,
+    /// File flushed with fdatasync by `DropPolicy::SyncData`.
+    drop_file: Option<Arc<File>>,
 }
 
 impl MMapBase {
     pub(crate) fn new(addr_hint: *mut u8, map_len: usize, prot: i32, flags: i32, fd: i32, map_off: i64) -> std::io::Result<Self> {
-        let map_ptr = unsafe {
-            let addr = addr_hint as *mut libc::c_void;
-            let ptr = mmap(addr, map_len, prot, flags, fd, map_off);
-            if ptr == libc::MAP_FAILED {
-                return Err(std::io::Error::last_os_error());
+        let map_ptr = if map_len == 0 {
+            std::ptr::NonNull::<u8>::dangling().as_ptr()
+        } else {
+            unsafe {
+                let addr = addr_hint as *mut libc::c_void;
+                let ptr = mmap(addr, map_len, prot, flags, fd, map_off);
+                if ptr == libc::MAP_FAILED {
+                    let errno = last_errno();
+                    return Err(MMapError::Mmap { errno, addr: addr as usize, len: map_len, prot, flags, fd, offset: map_off }.into());
+                }
+                ptr as *mut u8
             }
-            ptr
-        } as *mut u8;
-        Ok(Self { map_len, map_ptr })
+        };
+        let flags = flags & !libc::MAP_FIXED;
+        Ok(Self { map_len, map_ptr, prot, flags, fd, map_off, drop_policy: DropPolicy::None, drop_file: None })
     }
 
     /// Return length of the mapped region.
@@ -39,20 +219,113 @@ impl MMapBase {
         self.map_len
     }
 
+    /// Check if this is a MAP_SHARED file mapping.
+    pub(crate) fn is_shared_file(&self) -> bool {
+        self.fd >= 0 && self.flags & libc::MAP_ANON == 0 && self.flags & libc::MAP_SHARED != 0
+    }
+
+    pub(crate) fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// Set the policy applied by `unmap` and drop. `file` is flushed by
+    /// `DropPolicy::SyncData`, which fails without it.
+    pub(crate) fn set_drop_policy(&mut self, policy: DropPolicy, file: Option<Arc<File>>) -> std::io::Result<()> {
+        if policy == DropPolicy::SyncData && file.is_none() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "mapping does not own its file"));
+        }
+        self.drop_policy = policy;
+        self.drop_file = file;
+        Ok(())
+    }
+
+    /// Flush the region according to the drop policy.
+    fn flush_on_drop(&self) -> std::io::Result<()> {
+        let typ = match self.drop_policy {
+            DropPolicy::None => return Ok(()),
+            DropPolicy::Async => MSyncType::Async,
+            DropPolicy::Sync | DropPolicy::SyncData => MSyncType::Sync,
+        };
+        self.synchronize(typ)?;
+        match &self.drop_file {
+            Some(file) if self.drop_policy == DropPolicy::SyncData => file.sync_data(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Unmap the region, which is left empty.
+    fn release(&mut self) -> std::io::Result<()> {
+        let (addr, len) = (self.map_ptr, self.map_len);
+        if len == 0 {
+            return Ok(());
+        }
+        self.map_ptr = std::ptr::NonNull::<u8>::dangling().as_ptr();
+        self.map_len = 0;
+        unsafe {
+            let rc = munmap(addr as *mut libc::c_void, len);
+            if rc != 0 {
+                return Err(MMapError::Munmap { errno: last_errno(), addr: addr as usize, len }.into());
+            }
+        }
+        Ok(())
+    }
+
     /// Wraps the mprotect syscall which changes the protections of 
     /// the mapped pages. Note that a file descriptor opened read 
     /// only cannot made writable with this syscall, it will fail 
     /// with EACCES. 
-    pub(crate) fn protect(&self, prot: i32) -> std::io::Result<()> {
-        unsafe {
-            let rc = mprotect(self.map_ptr as *mut libc::c_void, self.map_len, prot);
-            if rc != 0 {
-                return Err(std::io::Error::last_os_error());
+    pub(crate) fn protect(&mut self, prot: i32) -> std::io::Result<()> {
+        if self.map_len > 0 {
+            unsafe {
+                let rc = mprotect(self.map_ptr as *mut libc::c_void, self.map_len, prot);
+                if rc != 0 {
+                    return Err(MMapError::Mprotect { errno: last_errno(), addr: self.map_ptr as usize, len: self.map_len, prot }.into());
+                }
             }
         }
+        self.prot = prot;
         Ok(())
     }
 
+    /// Change the length of the mapping, the kernel may move it to a new 
+    /// address. Pages added at the end are backed like the existing ones, 
+    /// i.e. a file mapping must only be grown after the file was extended.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn remap(&mut self, new_len: usize) -> std::io::Result<()> {
+        if self.map_len == 0 || new_len == 0 {
+            return self.replace(new_len);
+        }
+        let ptr = unsafe {
+            let ptr = libc::mremap(self.map_ptr as *mut libc::c_void, self.map_len, new_len, libc::MREMAP_MAYMOVE);
+            if ptr == libc::MAP_FAILED {
+                let (addr, old_len) = (self.map_ptr as usize, self.map_len);
+                return Err(MMapError::Mremap { errno: last_errno(), addr, old_len, new_len }.into());
+            }
+            ptr
+        } as *mut u8;
+        self.map_ptr = ptr;
+        self.map_len = new_len;
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn remap(&mut self, new_len: usize) -> std::io::Result<()> {
+        if self.map_len == 0 || new_len == 0 {
+            return self.replace(new_len);
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "mremap is not available on this platform"))
+    }
+
+    /// Replace an empty region by a new mapping of `new_len` bytes or unmap
+    /// the region if `new_len` is zero.
+    fn replace(&mut self, new_len: usize) -> std::io::Result<()> {
+        let mut new = Self::new(std::ptr::null_mut(), new_len, self.prot, self.flags, self.fd, self.map_off)?;
+        new.drop_policy = self.drop_policy;
+        new.drop_file = self.drop_file.clone();
+        let old = std::mem::replace(self, new);
+        old.unmap()
+    }
+
     /// Wraps the msync syscall, which flushes the modified pages back to
     /// the file system and updates the file timestamp.
     /// There are three types of synchronization:
@@ -60,13 +333,7 @@ impl MMapBase {
     /// * Sync: syscall blocks until write has finished
     /// * Invadliate: syscall invalidates all cached data 
     fn synchronize(&self, typ: MSyncType) -> std::io::Result<()> {
-        unsafe {
-            let rc = msync(self.map_ptr as *mut libc::c_void, self.map_len, typ.as_flag());
-            if rc != 0 {
-                return Err(std::io::Error::last_os_error());
-            }
-        }
-        Ok(())
+        msync_region(self.map_ptr, self.map_len, &typ)
     }
 
     /// Wraps the madvise syscall that allows a process that has knowledge about 
@@ -74,15 +341,39 @@ impl MMapBase {
     /// alter its virtual memory paging strategy depending on that advice which 
     /// might improve performance.
     fn madvise(&self, flag: i32) -> std::io::Result<()> {
-        unsafe {
-            let rc = madvise(self.map_ptr as *mut libc::c_void, self.map_len, flag);
-            if rc != 0 {
-                return Err(std::io::Error::last_os_error());
-            }
+        madvise_region(self.map_ptr, self.map_len, flag)
+    }
+
+    /// Map `file`, which must be the mapped file, again over the pages at
+    /// `addr`, which replaced pages of the mapping after a fault, see
+    /// `fault::guarded`. The fd the mapping was created with may have been
+    /// closed or reused since, so it is not used.
+    pub(crate) fn map_again(&self, file: &File, addr: *mut u8, len: usize) -> std::io::Result<()> {
+        use std::os::unix::prelude::AsRawFd;
+        let offset = self.map_off + (addr as usize - self.map_ptr as usize) as i64;
+        let (flags, fd) = (self.flags | libc::MAP_FIXED, file.as_raw_fd());
+        let ptr = unsafe { mmap(addr as *mut libc::c_void, len, self.prot, flags, fd, offset) };
+        if ptr == libc::MAP_FAILED {
+            let (errno, prot) = (last_errno(), self.prot);
+            return Err(MMapError::Mmap { errno, addr: addr as usize, len, prot, flags, fd, offset }.into());
         }
         Ok(())
     }
 
+    /// Like `MMap::try_read`, pages that faulted are mapped again if the
+    /// mapping owns its `file`.
+    pub(crate) fn try_read_guarded<R>(&self, range: std::ops::Range<usize>, file: Option<&File>, f: impl FnOnce(&[u8]) -> R) -> std::io::Result<R> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of the mapped region"));
+        }
+        let data = &self.as_slice()[range];
+        let map_again = |addr, len| match file {
+            Some(file) => self.map_again(file, addr, len),
+            None => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "mapping does not own its file")),
+        };
+        crate::fault::guarded(data.as_ptr(), data.len(), self.prot, map_again, || f(data))
+    }
+
     pub(crate) fn as_slice(&self) -> &[u8] {
         unsafe {
             std::slice::from_raw_parts(self.as_ptr(), self.len())
@@ -116,23 +407,23 @@ impl MMap for MMapBase {
     fn sync(&self, typ: self::MSyncType) -> std::io::Result<()> {
         self.synchronize(typ)
     }
-    fn unmap(self) -> std::io::Result<()> {
-        unsafe {
-            let rc = munmap(self.map_ptr as *mut libc::c_void, self.map_len);
-            if rc != 0 {
-                return Err(std::io::Error::last_os_error());
-            }
-        }
-        Ok(())
+    fn unmap(mut self) -> std::io::Result<()> {
+        let flushed = self.flush_on_drop();
+        let unmapped = self.release();
+        flushed.and(unmapped)
     }
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
+        check_advice(&config, self.is_shared_file())?;
         self.madvise(config.value())
     }
+    fn is_shared_file(&self) -> bool {
+        MMapBase::is_shared_file(self)
+    }
 }
 
 impl MMapMut for MMapBase {
     fn as_mut_ptr(&mut self) -> *mut u8 {
-        self.map_ptr as *mut u8
+        self.map_ptr
     }
 }
 impl MMapExec for MMapBase {}
@@ -140,16 +431,22 @@ impl MMapExecMut for MMapBase {}
 
 impl Drop for MMapBase {
     fn drop(&mut self) {
-        unsafe {
-            // Basically the same as unmap but we do not handle the error
-            // here, as drop cannot return a result. 
-            //
-            // We could panic but this can create a situation were we panic 
-            // during stack unwinding due to another panic, i.e. a double 
-            // panic which will result in the process being killed with a SIGILL signal.
-            // 
-            // If error handling is desired, call unmap instead of drop.
-            munmap(self.map_ptr as *mut libc::c_void, self.map_len);
+        // Basically the same as unmap but the errors are passed to the
+        // drop error hook, as drop cannot return a result. 
+        //
+        // We could panic but this can create a situation were we panic 
+        // during stack unwinding due to another panic, i.e. a double 
+        // panic which will result in the process being killed with a SIGILL signal.
+        // 
+        // If error handling is desired, call unmap instead of drop.
+        if self.map_len == 0 {
+            return;
+        }
+        if let Err(e) = self.flush_on_drop() {
+            report_drop_error(e);
+        }
+        if let Err(e) = self.release() {
+            report_drop_error(e);
         }
     }
 }
@@ -165,3 +462,32 @@ impl MSyncType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn drop_errors_are_reported() {
+        static ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        // the hook is global, other tests drop mappings concurrently.
+        let me = std::thread::current().id();
+        set_drop_error_hook(move |e| {
+            if std::thread::current().id() == me {
+                ERRORS.lock().unwrap().push(e.to_string());
+                // the hook is not called with the lock held.
+                clear_drop_error_hook();
+            }
+        });
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        let mut base = MMapBase::new(std::ptr::null_mut(), 4096, libc::PROT_READ, flags, -1, 0).unwrap();
+        // fdatasync is not supported for /dev/null.
+        let file = Arc::new(File::open("/dev/null").unwrap());
+        base.set_drop_policy(DropPolicy::SyncData, Some(file)).unwrap();
+        drop(base);
+        clear_drop_error_hook();
+        let expected = std::io::Error::from_raw_os_error(libc::EINVAL).to_string();
+        assert_eq!(*ERRORS.lock().unwrap(), vec![expected]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::io::seek_position;
+use crate::MMapHandle;
+use std::borrow::Cow;
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Reader presenting a sequence of mappings, e.g. the segments of a rotated
+/// log file, as one stream of bytes. Like `MMapCursor` the mappings can be
+/// owned or shared through an `Arc`.
+pub struct ChainReader<H> {
+    cur: u64,
+    parts: Vec<H>,
+    /// Offset of the first byte of every part in the stream, followed by
+    /// the total length.
+    starts: Vec<u64>,
+}
+
+impl<H: MMapHandle> ChainReader<H> {
+    pub fn new(parts: Vec<H>) -> Self {
+        let mut starts = Vec::with_capacity(parts.len() + 1);
+        let mut off = 0;
+        for p in parts.iter() {
+            starts.push(off);
+            off += p.mmap().len() as u64;
+        }
+        starts.push(off);
+        Self { cur: 0, parts, starts }
+    }
+
+    /// Return the total length of all mappings.
+    pub fn len(&self) -> u64 {
+        *self.starts.last().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the current position of the reader.
+    pub fn position(&self) -> u64 {
+        self.cur
+    }
+
+    pub fn parts(&self) -> &[H] {
+        &self.parts
+    }
+
+    /// Return the mappings, dropping the reader.
+    pub fn into_inner(self) -> Vec<H> {
+        self.parts
+    }
+
+    /// Return the bytes at `range` of the stream. They are borrowed if the
+    /// range lies within one mapping and copied if it spans several.
+    pub fn get(&self, range: Range<u64>) -> std::io::Result<Cow<'_, [u8]>> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of the stream"));
+        }
+        if range.is_empty() {
+            return Ok(Cow::Borrowed(&[]));
+        }
+        let (first, off) = self.locate(range.start);
+        let data = self.parts[first].mmap();
+        let len = (range.end - range.start) as usize;
+        if off + len <= data.len() {
+            return Ok(Cow::Borrowed(&data[off..off + len]));
+        }
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&data[off..]);
+        for p in self.parts[first + 1..].iter() {
+            let rem = len - buf.len();
+            let data = p.mmap();
+            buf.extend_from_slice(&data[..std::cmp::min(rem, data.len())]);
+            if buf.len() == len {
+                break;
+            }
+        }
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Return the index of the mapping containing byte `pos` of the stream
+    /// and the offset within it, `pos` must be less than `len`.
+    fn locate(&self, pos: u64) -> (usize, usize) {
+        // the last start not greater than pos, skipping empty mappings.
+        let n = self.starts.partition_point(|s| *s <= pos) - 1;
+        (n, (pos - self.starts[n]) as usize)
+    }
+
+    /// Return the rest of the mapping at the current position.
+    fn current(&self) -> &[u8] {
+        if self.cur >= self.len() {
+            return &[];
+        }
+        let (n, off) = self.locate(self.cur);
+        &self.parts[n].mmap()[off..]
+    }
+}
+
+impl<H: MMapHandle> Seek for ChainReader<H> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.cur = seek_position(self.cur, self.len() as usize, pos)?;
+        Ok(self.cur)
+    }
+}
+
+impl<H: MMapHandle> Read for ChainReader<H> {
+    /// Reads across the boundaries of the mappings until `buf` is full or
+    /// the end of the stream is reached.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            let src = self.current();
+            if src.is_empty() {
+                break;
+            }
+            let k = std::cmp::min(src.len(), buf.len() - n);
+            buf[n..n + k].copy_from_slice(&src[..k]);
+            n += k;
+            self.cur += k as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl<H: MMapHandle> BufRead for ChainReader<H> {
+    /// Returns the rest of the current mapping, no bytes are copied.
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(self.current())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cur += amt as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddrHint, AnonMMap, AnonMMapMut, MMapConfig};
+    use std::sync::Arc;
+
+    fn part(bytes: &[u8]) -> AnonMMap {
+        let mut mmap = AnonMMapMut::new(AddrHint::None, bytes.len(), MMapConfig::new().map_private()).unwrap();
+        mmap.copy_from_slice(bytes);
+        mmap.try_into().unwrap()
+    }
+
+    fn chain() -> ChainReader<AnonMMap> {
+        ChainReader::new(vec![part(b"first line\nsec"), part(b"ond line\n"), part(b"third line\n")])
+    }
+
+    #[test]
+    fn read_across_parts() {
+        let mut r = chain();
+        assert_eq!(r.len(), 34);
+        let mut s = String::new();
+        r.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "first line\nsecond line\nthird line\n");
+        assert_eq!(r.read(&mut [0u8; 4]).unwrap(), 0);
+
+        r.seek(SeekFrom::Start(0)).unwrap();
+        let lines = r.lines().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines, ["first line", "second line", "third line"]);
+    }
+
+    #[test]
+    fn seek_and_fill_buf() {
+        let mut r = chain();
+        r.seek(SeekFrom::End(-11)).unwrap();
+        assert_eq!(r.fill_buf().unwrap(), b"third line\n");
+        r.seek(SeekFrom::Current(-2)).unwrap();
+        assert_eq!(r.fill_buf().unwrap(), b"e\n");
+        r.consume(2);
+        assert_eq!(r.fill_buf().unwrap(), b"third line\n");
+        assert!(r.seek(SeekFrom::Current(-100)).is_err());
+        r.seek(SeekFrom::Start(100)).unwrap();
+        assert!(r.fill_buf().unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_borrows_or_copies() {
+        let r = ChainReader::new(vec![Arc::new(part(b"abc")), Arc::new(part(b"defg"))]);
+        assert!(matches!(r.get(0..3).unwrap(), Cow::Borrowed(b"abc")));
+        assert!(matches!(r.get(3..5).unwrap(), Cow::Borrowed(b"de")));
+        let spanning = r.get(1..6).unwrap();
+        assert!(matches!(spanning, Cow::Owned(_)));
+        assert_eq!(&spanning[..], b"bcdef");
+        assert!(r.get(5..5).unwrap().is_empty());
+        assert_eq!(r.get(0..8).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{base, base::MMapBase, MAdviseConfig, MMap, MSyncType};
+use crate::error::{last_errno, MMapError};
+use std::fs::File;
+use std::ops::{Deref, Range};
+use std::os::unix::prelude::AsRawFd;
+
+/// Read only mapping of several files placed back to back in one virtually
+/// contiguous region, e.g. to search the segment files of a sharded data
+/// set as a single slice without copying them.
+///
+/// Every file starts on a page boundary, the gap between the end of a file
+/// and the next page boundary reads as zeros. Use `segments` to translate
+/// between offsets in the mapping and offsets in the files.
+pub struct ConcatMap {
+    inner: MMapBase,
+    segments: Vec<Segment>,
+}
+
+// SAFETY: see the thread safety notes on `MMap`.
+unsafe impl Send for ConcatMap {}
+unsafe impl Sync for ConcatMap {}
+
+/// Position of a file in a `ConcatMap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// Offset of the first byte of the file in the mapping.
+    pub offset: usize,
+    /// Length of the file.
+    pub len: usize,
+}
+
+impl ConcatMap {
+    /// Reserve an address range large enough for all `files` and map each
+    /// of them into it with MAP_FIXED.
+    pub fn new(files: &[File]) -> std::io::Result<Self> {
+        let ps = base::get_page_size() as usize;
+        let mut segments = Vec::with_capacity(files.len());
+        let mut len = 0;
+        for file in files {
+            let flen = file.metadata()?.len() as usize;
+            segments.push(Segment { offset: len, len: flen });
+            len += flen.div_ceil(ps) * ps;
+        }
+        // the reservation is never accessible by itself, the files are
+        // mapped over it. It also keeps the region from being used by
+        // other mappings while the files are mapped one after another.
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        let inner = MMapBase::new(std::ptr::null_mut(), len, libc::PROT_NONE, flags, -1, 0)?;
+        for (file, seg) in files.iter().zip(segments.iter()) {
+            if seg.len == 0 {
+                continue;
+            }
+            unsafe {
+                let addr = inner.as_ptr().add(seg.offset) as *mut libc::c_void;
+                let flags = libc::MAP_SHARED | libc::MAP_FIXED;
+                let ptr = libc::mmap(addr, seg.len, libc::PROT_READ, flags, file.as_raw_fd(), 0);
+                if ptr == libc::MAP_FAILED {
+                    // dropping the reservation unmaps the files mapped so far.
+                    let (errno, fd) = (last_errno(), file.as_raw_fd());
+                    return Err(MMapError::Mmap { errno, addr: addr as usize, len: seg.len, prot: libc::PROT_READ, flags, fd, offset: 0 }.into());
+                }
+            }
+        }
+        Ok(Self { inner, segments })
+    }
+
+    /// Return the position of every file in the mapping, in the order they
+    /// were passed to `new`.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Return the contents of file `n`.
+    pub fn segment(&self, n: usize) -> Option<&[u8]> {
+        let seg = self.segments.get(n)?;
+        Some(&self[seg.offset..seg.offset + seg.len])
+    }
+
+    /// Translate an offset in the mapping into the index of the file and
+    /// the offset within the file. Returns None for offsets in the padding.
+    pub fn locate(&self, off: usize) -> Option<(usize, usize)> {
+        let n = self.segments.partition_point(|s| s.offset <= off).checked_sub(1)?;
+        // empty files share their offset with the next file.
+        let n = self.segments[..=n].iter().rposition(|s| s.len > 0 && s.offset <= off)?;
+        let seg = &self.segments[n];
+        if off - seg.offset < seg.len {
+            Some((n, off - seg.offset))
+        } else {
+            None
+        }
+    }
+}
+
+impl Deref for ConcatMap {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl MMap for ConcatMap {
+    fn as_ptr(&self) -> *const u8 {
+        self.inner.as_ptr()
+    }
+    fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
+        self.inner.sync(typ)
+    }
+    fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
+        self.inner.advise(config)
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        let (addr, len) = base::page_range(self.as_ptr(), self.len(), range)?;
+        base::populate_region(addr, len, false, || Ok(base::Extent::Unknown))
+    }
+    fn unmap(self) -> std::io::Result<()> {
+        self.inner.unmap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestFile;
+
+    /// Create a file of `lens[i]` bytes with value `i + 1` for every `i`.
+    fn test_files(lens: &[usize]) -> Vec<TestFile> {
+        lens.iter().enumerate().map(|(i, len)| TestFile::new(&vec![i as u8 + 1; *len])).collect()
+    }
+
+    fn open(files: &[TestFile]) -> Vec<File> {
+        files.iter().map(|f| File::open(&f.path).unwrap()).collect()
+    }
+
+    #[test]
+    fn files_are_contiguous() {
+        let ps = base::get_page_size() as usize;
+        let lens = [100, 0, 2 * ps + 1, 3 * ps];
+        let tf = test_files(&lens);
+        let map = ConcatMap::new(&open(&tf)).unwrap();
+        assert_eq!(map.len(), ps + 3 * ps + 3 * ps);
+        let offsets = map.segments().iter().map(|s| s.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, ps, ps, 4 * ps]);
+        for (i, len) in lens.iter().enumerate() {
+            let seg = map.segment(i).unwrap();
+            assert_eq!(seg.len(), *len);
+            assert!(seg.iter().all(|b| *b == i as u8 + 1));
+        }
+        // padding reads as zeros.
+        assert!(map[100..ps].iter().all(|b| *b == 0));
+        assert!(map[3 * ps + 1..4 * ps].iter().all(|b| *b == 0));
+
+        assert_eq!(map.locate(99), Some((0, 99)));
+        assert_eq!(map.locate(100), None);
+        assert_eq!(map.locate(ps), Some((2, 0)));
+        assert_eq!(map.locate(4 * ps + 5), Some((3, 5)));
+        assert_eq!(map.locate(7 * ps), None);
+    }
+
+    #[test]
+    fn search_across_files() {
+        let tf = test_files(&[10, 20]);
+        let map = ConcatMap::new(&open(&tf)).unwrap();
+        let off = memchr::memchr(2, &map).unwrap();
+        assert_eq!(map.locate(off), Some((1, 0)));
+        assert!(ConcatMap::new(&[]).unwrap().is_empty());
+    }
+}
@@ -1,5 +1,3 @@
-use libc;
-
 #[derive(Clone, Copy, Debug)]
 pub struct MMapConfig {
     flags: i32,    
@@ -10,6 +8,12 @@ pub struct MAdviseConfig {
     flags: i32,    
 }
 
+impl Default for MMapConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MMapConfig {
     /// Create empty option set.
     pub fn new() -> Self {
@@ -22,22 +26,29 @@ impl MMapConfig {
 
     /// Utility function.
     fn set_flag(mut self, f: i32) -> Self {
-        self.flags = self.flags | f;
+        self.flags |= f;
         self
     }
 
+    /// Utility function, the mapping is either shared or private.
+    fn set_type(mut self, f: i32) -> Self {
+        self.flags &= !(libc::MAP_SHARED | libc::MAP_PRIVATE);
+        self.set_flag(f)
+    }
+
     /// Shared mapping, writes are visible to other processes mapping the 
     /// same region and (if not anon) are carried through to the file 
     /// system (the timing of this is subject to OS buffering).
+    /// Replaces `map_private`.
     pub fn map_shared(self) -> Self {
         // TODO: on linux we should maybe use MAP_SHARED_VALIDATE instead?
-        self.set_flag(libc::MAP_SHARED)
+        self.set_type(libc::MAP_SHARED)
     }
 
     /// Private copy-on-write mapping. Writes are not visible to other 
-    /// processes or the file system.
+    /// processes or the file system. Replaces `map_shared`.
     pub fn map_private(self) -> Self {
-        self.set_flag(libc::MAP_PRIVATE)
+        self.set_type(libc::MAP_PRIVATE)
     }
 
     /// Do not permit the system to choose any other address as the one 
@@ -51,6 +62,13 @@ impl MMapConfig {
         self.set_flag(libc::MAP_FIXED)
     }
 
+    /// Prefault all pages of the mapping in the mmap call, which then 
+    /// blocks until the whole file has been read.
+    #[cfg(target_os = "linux")] 
+    pub fn map_populate(self) -> Self {
+        self.set_flag(libc::MAP_POPULATE)
+    }
+
     /// Allow mapping to be both, writable and executable, when the hardened 
     /// runtime is enabled. 
     #[cfg(target_os = "macos")] 
@@ -72,6 +90,12 @@ impl MMapConfig {
     }
 }
 
+impl Default for MAdviseConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MAdviseConfig {
     /// Create empty option set.
     pub fn new() -> Self {
@@ -82,31 +106,38 @@ impl MAdviseConfig {
         self.flags
     }
 
+    /// Check if the advice may discard the contents of the pages.
+    pub(crate) fn discards_pages(&self) -> bool {
+        #[cfg(target_os = "macos")]
+        if self.flags == libc::MADV_FREE {
+            return true;
+        }
+        // the advice values are enumerated, not bits.
+        self.flags == libc::MADV_DONTNEED
+    }
+
     /// Utility function.
     fn set_flag(mut self, f: i32) -> Self {
-        self.flags = self.flags | f;
+        self.flags |= f;
         self
     }
 
     /// Indicate to the kernel that application expects to access memory
     /// in a sequential manner.
     pub fn madv_sequential(self) -> Self {
-        self.set_flag(libc::MADV_SEQUENTIAL);
-        self
+        self.set_flag(libc::MADV_SEQUENTIAL)
     } 
 
     /// Indicate to the kernel that application expects to access memory
     /// in a random manner.
     pub fn madv_random(self) -> Self {
-        self.set_flag(libc::MADV_RANDOM);
-        self
+        self.set_flag(libc::MADV_RANDOM)
     } 
 
     /// Indicate to the kernel that the applications intends to access this 
     /// address soon. 
     pub fn madv_willneed(self) -> Self {
-        self.set_flag(libc::MADV_WILLNEED);
-        self
+        self.set_flag(libc::MADV_WILLNEED)
     }
 
     /// Indicate to the kernel that the applications does not need this 
@@ -117,18 +148,37 @@ impl MAdviseConfig {
     /// succeed but the memory will be repopulated with the underlying file 
     /// data, if this is a file mapping, or zero mapped pages for anonymous
     /// and private mappings.
+    ///
+    /// As the latter would change memory that may be borrowed as `&[u8]`, 
+    /// the advice is rejected with InvalidInput for all mappings but 
+    /// shared file mappings, see `MMap::is_shared_file`.
     pub fn madv_dontneed(self) -> Self {
-        self.set_flag(libc::MADV_DONTNEED);
-        self
+        self.set_flag(libc::MADV_DONTNEED)
+    }
+
+    /// Populate (prefault) the page tables readable, faulting in all pages 
+    /// of the range. Requires Linux 5.14, the advice fails with EINVAL on
+    /// older kernels. See also `MMap::populate_read`.
+    #[cfg(target_os = "linux")] 
+    pub fn madv_populate_read(self) -> Self {
+        self.set_flag(libc::MADV_POPULATE_READ)
+    }
+
+    /// Populate (prefault) the page tables writable, faulting in all pages
+    /// of the range. Requires Linux 5.14, the advice fails with EINVAL on
+    /// older kernels. See also `MMapMut::populate_write`.
+    #[cfg(target_os = "linux")] 
+    pub fn madv_populate_write(self) -> Self {
+        self.set_flag(libc::MADV_POPULATE_WRITE)
     }
 
     /// Indicate to the kernel that this address range is not needed any 
     /// more and the mapped pages can be reused right away. The mapped
-    /// memory will remain valid. (Like madv_dontneed on Linux).
+    /// memory will remain valid. (Like madv_dontneed on Linux, and 
+    /// rejected for the same mappings).
     #[cfg(target_os = "macos")] 
     pub fn madv_free(self) -> Self {
-        self.set_flag(libc::MADV_FREE);
-        self
+        self.set_flag(libc::MADV_FREE)
     }
 
     /// Tell the kernel that the mapped pages need to be zeroed out if the 
@@ -137,7 +187,6 @@ impl MAdviseConfig {
     /// the application quits.
     #[cfg(target_os = "macos")] 
     pub fn madv_zero_wired_pages(self) -> Self {
-        self.set_flag(libc::MADV_ZERO_WIRED_PAGES);
-        self
+        self.set_flag(libc::MADV_ZERO_WIRED_PAGES)
     }
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+/// Failed syscall with its errno and arguments.
+///
+/// Operations of this crate return `std::io::Error`. When a syscall fails
+/// the error wraps an `MMapError`, whose `Display` names the syscall and
+/// its arguments, and whose kind matches the errno.
+///
+/// As the error is a custom one, `raw_os_error()` returns `None` for it.
+/// Use `from_io` to get the details back, including the errno, which is
+/// the same as `err.get_ref().and_then(|e| e.downcast_ref::<MMapError>())`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MMapError {
+    Mmap { errno: i32, addr: usize, len: usize, prot: i32, flags: i32, fd: i32, offset: i64 },
+    Munmap { errno: i32, addr: usize, len: usize },
+    Mprotect { errno: i32, addr: usize, len: usize, prot: i32 },
+    Mremap { errno: i32, addr: usize, old_len: usize, new_len: usize },
+    Msync { errno: i32, addr: usize, len: usize, flags: i32 },
+    Madvise { errno: i32, addr: usize, len: usize, advice: i32 },
+    Mincore { errno: i32, addr: usize, len: usize },
+    Mlock { errno: i32, addr: usize, len: usize },
+    Munlock { errno: i32, addr: usize, len: usize },
+    Fallocate { errno: i32, fd: i32, mode: i32, offset: i64, len: i64 },
+    Fadvise { errno: i32, fd: i32, offset: i64, len: i64, advice: i32 },
+    Fstatfs { errno: i32, fd: i32 },
+    Linkat { errno: i32, fd: i32 },
+    Sigaction { errno: i32, signal: i32 },
+}
+
+impl MMapError {
+    /// Return the errno the syscall failed with.
+    pub fn errno(&self) -> i32 {
+        match *self {
+            Self::Mmap { errno, .. }
+            | Self::Munmap { errno, .. }
+            | Self::Mprotect { errno, .. }
+            | Self::Mremap { errno, .. }
+            | Self::Msync { errno, .. }
+            | Self::Madvise { errno, .. }
+            | Self::Mincore { errno, .. }
+            | Self::Mlock { errno, .. }
+            | Self::Munlock { errno, .. }
+            | Self::Fallocate { errno, .. }
+            | Self::Fadvise { errno, .. }
+            | Self::Fstatfs { errno, .. }
+            | Self::Linkat { errno, .. }
+            | Self::Sigaction { errno, .. } => errno,
+        }
+    }
+
+    /// Return the name of the failed syscall.
+    pub fn syscall(&self) -> &'static str {
+        match self {
+            Self::Mmap { .. } => "mmap",
+            Self::Munmap { .. } => "munmap",
+            Self::Mprotect { .. } => "mprotect",
+            Self::Mremap { .. } => "mremap",
+            Self::Msync { .. } => "msync",
+            Self::Madvise { .. } => "madvise",
+            Self::Mincore { .. } => "mincore",
+            Self::Mlock { .. } => "mlock",
+            Self::Munlock { .. } => "munlock",
+            Self::Fallocate { .. } => "fallocate",
+            Self::Fadvise { .. } => "posix_fadvise",
+            Self::Fstatfs { .. } => "fstatfs",
+            Self::Linkat { .. } => "linkat",
+            Self::Sigaction { .. } => "sigaction",
+        }
+    }
+
+    /// Return the syscall error wrapped in `err`, if any. Use this instead of
+    /// `raw_os_error`, which is `None` for the errors of this crate.
+    pub fn from_io(err: &std::io::Error) -> Option<&MMapError> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+/// Return the errno of `err`, either a plain OS error or an `MMapError`.
+pub(crate) fn errno_of(err: &std::io::Error) -> Option<i32> {
+    err.raw_os_error().or_else(|| MMapError::from_io(err).map(|e| e.errno()))
+}
+
+/// Return the errno of the last failed syscall of this thread.
+pub(crate) fn last_errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+impl std::fmt::Display for MMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}(", self.syscall())?;
+        match *self {
+            Self::Mmap { addr, len, prot, flags, fd, offset, .. } => {
+                write!(f, "addr={:#x}, len={}, prot={:#x}, flags={:#x}, fd={}, offset={}", addr, len, prot, flags, fd, offset)?
+            }
+            Self::Mprotect { addr, len, prot, .. } => write!(f, "addr={:#x}, len={}, prot={:#x}", addr, len, prot)?,
+            Self::Mremap { addr, old_len, new_len, .. } => {
+                write!(f, "addr={:#x}, old_len={}, new_len={}", addr, old_len, new_len)?
+            }
+            Self::Msync { addr, len, flags, .. } => write!(f, "addr={:#x}, len={}, flags={:#x}", addr, len, flags)?,
+            Self::Madvise { addr, len, advice, .. } => write!(f, "addr={:#x}, len={}, advice={}", addr, len, advice)?,
+            Self::Munmap { addr, len, .. }
+            | Self::Mincore { addr, len, .. }
+            | Self::Mlock { addr, len, .. }
+            | Self::Munlock { addr, len, .. } => write!(f, "addr={:#x}, len={}", addr, len)?,
+            Self::Fallocate { fd, mode, offset, len, .. } => {
+                write!(f, "fd={}, mode={:#x}, offset={}, len={}", fd, mode, offset, len)?
+            }
+            Self::Fadvise { fd, offset, len, advice, .. } => {
+                write!(f, "fd={}, offset={}, len={}, advice={}", fd, offset, len, advice)?
+            }
+            Self::Fstatfs { fd, .. } | Self::Linkat { fd, .. } => write!(f, "fd={}", fd)?,
+            Self::Sigaction { signal, .. } => write!(f, "signal={}", signal)?,
+        }
+        write!(f, ") failed: {}", std::io::Error::from_raw_os_error(self.errno()))
+    }
+}
+
+impl std::error::Error for MMapError {}
+
+/// The kind of the `io::Error` follows the errno, but `raw_os_error()`
+/// returns `None`, see `MMapError::from_io`.
+impl From<MMapError> for std::io::Error {
+    fn from(err: MMapError) -> Self {
+        let kind = std::io::Error::from_raw_os_error(err.errno()).kind();
+        std::io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_conversion() {
+        let err = MMapError::Msync { errno: libc::EINVAL, addr: 0x1000, len: 4096, flags: libc::MS_SYNC };
+        let msg = err.to_string();
+        assert!(msg.starts_with("msync(addr=0x1000, len=4096, flags="), "{}", msg);
+        let io: std::io::Error = err.into();
+        assert_eq!(io.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(MMapError::from_io(&io), Some(&err));
+        assert_eq!(errno_of(&io), Some(libc::EINVAL));
+        assert_eq!(errno_of(&std::io::Error::from_raw_os_error(libc::ENOMEM)), Some(libc::ENOMEM));
+        assert!(MMapError::from_io(&std::io::Error::other("x")).is_none());
+        assert_eq!(io.raw_os_error(), None);
+
+        let err = MMapError::Fadvise { errno: libc::EBADF, fd: 3, offset: 0, len: 4096, advice: 4 };
+        assert!(err.to_string().starts_with("posix_fadvise(fd=3, offset=0, len=4096, advice=4) failed: "), "{}", err);
+    }
+}
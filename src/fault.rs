@@ -0,0 +1,230 @@
+use std::cell::Cell;
+use std::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use crate::error::{last_errno, MMapError};
+
+// Range of addresses the current thread accesses through `guarded` with the
+// protection of the mapping, and the range of pages replaced after a fault.
+// Const initialized without destructor, so they can be used from the signal
+// handler.
+thread_local! {
+    static GUARD: Cell<(usize, usize, i32)> = const { Cell::new((0, 0, 0)) };
+    static REPLACED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// SIGBUS action that was installed before ours, set once ours is installed.
+static PREV_ACTION: OnceLock<libc::sigaction> = OnceLock::new();
+/// Serializes installing the handler.
+static INSTALL: Mutex<()> = Mutex::new(());
+/// Page size, sysconf cannot be called from the signal handler.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Install the process wide SIGBUS handler used by `MMap::try_read` and
+/// `MMap::try_copy_from`. It is installed on their first use, calling this
+/// function early avoids racing with other code installing handlers.
+///
+/// Faults outside of a guarded access are passed on to the handler that was
+/// installed before, or terminate the process as usual.
+pub fn install_sigbus_handler() -> std::io::Result<()> {
+    if is_installed() {
+        return Ok(());
+    }
+    let _lock = INSTALL.lock().unwrap_or_else(|e| e.into_inner());
+    if is_installed() {
+        return Ok(());
+    }
+    PAGE_SIZE.store(crate::base::get_page_size() as usize, Ordering::Relaxed);
+    let prev = unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigbus as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut prev: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGBUS, &action, &mut prev) != 0 {
+            // nothing was installed, the next call tries again.
+            return Err(MMapError::Sigaction { errno: last_errno(), signal: libc::SIGBUS }.into());
+        }
+        prev
+    };
+    // until it is set, faults are passed on to the default action, which
+    // is fine as no access is guarded before this function returns.
+    let _ = PREV_ACTION.set(prev);
+    Ok(())
+}
+
+fn is_installed() -> bool {
+    PREV_ACTION.get().is_some()
+}
+
+/// Run `f`, which accesses the region at `addr` with length `len` of a
+/// mapping with protection `prot`, and report a SIGBUS raised by an access
+/// to the region as an error.
+///
+/// A page that faulted is replaced by a page of zeros with protection `prot`
+/// so that `f` can run to completion. Afterwards `map_again` is called with
+/// the range of replaced pages to map the file over them again. If that
+/// fails, e.g. because the mapping does not own its file, the pages stay
+/// and read as zeros. Making them inaccessible instead would turn the next
+/// read through `Deref` into a SIGSEGV.
+pub(crate) fn guarded<R>(
+    addr: *const u8,
+    len: usize,
+    prot: i32,
+    map_again: impl FnOnce(*mut u8, usize) -> std::io::Result<()>,
+    f: impl FnOnce() -> R,
+) -> std::io::Result<R> {
+    install_sigbus_handler()?;
+    let restore = Restore {
+        guard: GUARD.with(|g| g.replace((addr as usize, addr as usize + len, prot))),
+        replaced: REPLACED.with(|r| r.replace((0, 0))),
+    };
+    compiler_fence(Ordering::SeqCst);
+    let res = f();
+    compiler_fence(Ordering::SeqCst);
+    let (begin, end) = REPLACED.with(|r| r.get());
+    drop(restore);
+    if begin < end {
+        let (page, len) = (begin as *mut u8, end - begin);
+        let _ = map_again(page, len);
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "mapped file is shorter than the accessed range"));
+    }
+    Ok(res)
+}
+
+/// Restores the guard of an enclosing `guarded` call, also if `f` panics.
+/// Only the innermost range is guarded.
+struct Restore {
+    guard: (usize, usize, i32),
+    replaced: (usize, usize),
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        GUARD.with(|g| g.set(self.guard));
+        REPLACED.with(|r| r.set(self.replaced));
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn fault_addr(info: *mut libc::siginfo_t) -> usize {
+    (*info).si_addr() as usize
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn fault_addr(info: *mut libc::siginfo_t) -> usize {
+    (*info).si_addr as usize
+}
+
+extern "C" fn handle_sigbus(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    unsafe {
+        let addr = fault_addr(info);
+        let (begin, end, prot) = GUARD.with(|g| g.get());
+        if addr >= begin && addr < end {
+            let ps = PAGE_SIZE.load(Ordering::Relaxed);
+            let page = addr - addr % ps;
+            let flags = libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_FIXED;
+            if libc::mmap(page as *mut libc::c_void, ps, prot, flags, -1, 0) != libc::MAP_FAILED {
+                REPLACED.with(|r| {
+                    let (first, last) = r.get();
+                    r.set(if first < last { (first.min(page), last.max(page + ps)) } else { (page, page + ps) });
+                });
+                return;
+            }
+        }
+        chain(sig, info, ctx);
+    }
+}
+
+/// Pass a fault we do not handle to the previous handler.
+unsafe fn chain(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    let prev = match PREV_ACTION.get() {
+        Some(p) if p.sa_sigaction != libc::SIG_DFL && p.sa_sigaction != libc::SIG_IGN => p,
+        _ => {
+            // restore the default action, returning re-executes the faulting
+            // instruction which then terminates the process.
+            libc::signal(sig, libc::SIG_DFL);
+            return;
+        }
+    };
+    if prev.sa_flags & libc::SA_SIGINFO != 0 {
+        let f: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) = std::mem::transmute(prev.sa_sigaction);
+        f(sig, info, ctx);
+    } else {
+        let f: extern "C" fn(libc::c_int) = std::mem::transmute(prev.sa_sigaction);
+        f(sig);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AddrHint, FileMMap, FileMMapMut, MMap, MMapConfig};
+    use crate::testutil::TestFile;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn read_truncated_file() {
+        let ps = crate::base::get_page_size() as usize;
+        let tf = TestFile::new(&vec![0x42; 10 * ps]);
+        let mmap = FileMMap::new_owned(AddrHint::None, MMapConfig::new().map_shared(), tf.fp.try_clone().unwrap(), 0).unwrap();
+        assert_eq!(mmap.try_copy_from(0..10 * ps).unwrap(), vec![0x42; 10 * ps]);
+
+        // another process truncating the file has the same effect.
+        tf.fp.set_len(2 * ps as u64).unwrap();
+        let e = mmap.try_read(5 * ps..6 * ps, |s| s[100]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        let e = mmap.try_copy_from(0..10 * ps).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        let sum = mmap.try_read(0..2 * ps, |s| s.iter().map(|b| *b as usize).sum::<usize>()).unwrap();
+        assert_eq!(sum, 0x42 * 2 * ps);
+        // pages that faulted are mapped from the file again.
+        let e = mmap.try_read(5 * ps..6 * ps, |s| s[100]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn faulted_pages_are_mapped_again() {
+        let ps = crate::base::get_page_size() as usize;
+        let tf = TestFile::new(&vec![1; 4 * ps]);
+        let file = tf.fp.try_clone().unwrap();
+        let mut mmap = FileMMapMut::new_owned(AddrHint::None, MMapConfig::new().map_shared(), file, 0).unwrap();
+        tf.fp.set_len(ps as u64).unwrap();
+        // the closure itself reads the removed pages.
+        let e = mmap.try_read(0..4 * ps, |s| s[2 * ps] + s[3 * ps]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        tf.fp.set_len(4 * ps as u64).unwrap();
+        tf.fp.write_all_at(b"x", 3 * ps as u64).unwrap();
+        assert_eq!(mmap[3 * ps], b'x');
+        assert_eq!(mmap[2 * ps], 0);
+        // the pages are writable and shared with the file again.
+        mmap[2 * ps] = b'y';
+        let mut buf = [0];
+        tf.fp.read_exact_at(&mut buf, 2 * ps as u64).unwrap();
+        assert_eq!(buf, *b"y");
+    }
+
+    #[test]
+    fn borrowed_file_is_not_mapped_again() {
+        let ps = crate::base::get_page_size() as usize;
+        let tf = TestFile::new(&vec![1; 4 * ps]);
+        let file = tf.fp.try_clone().unwrap();
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &file, 0).unwrap();
+        drop(file);
+        tf.fp.set_len(ps as u64).unwrap();
+        let e = mmap.try_read(0..4 * ps, |s| s[3 * ps]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        // the faulting page reads as zeros instead of raising SIGSEGV.
+        assert_eq!(mmap[3 * ps], 0);
+        assert_eq!(mmap[0], 1);
+    }
+
+    #[test]
+    fn nested_guards() {
+        let ps = crate::base::get_page_size() as usize;
+        let tf = TestFile::new(&vec![1; 4 * ps]);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        tf.fp.set_len(ps as u64).unwrap();
+        let outer = mmap.try_read(0..ps, |_| mmap.try_copy_from(3 * ps..4 * ps).is_err()).unwrap();
+        assert!(outer);
+    }
+}
@@ -1,17 +1,163 @@
-use crate::{MMap, MMapMut, MSyncType, MMapConfig, base::MMapBase, MAdviseConfig, AddrHint};
-use std::ops::{Deref, DerefMut};
-use std::fs::File;
+use crate::{MMap, MMapMut, MMapResize, MSyncType, MMapConfig, base, base::{MMapBase, Extent}, MAdviseConfig, AddrHint, DropPolicy};
+use std::ops::{Deref, DerefMut, Range};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
 use std::os::unix::prelude::AsRawFd;
+use crate::records::{self, Lines, FindAll};
+#[cfg(target_os = "linux")]
+use crate::error::{last_errno, MMapError};
+use std::sync::Arc;
+
+/// File of a mapping that owns it, see `FileMMap::new_owned`.
+struct Backing {
+    file: Arc<File>,
+    off: u64,
+}
+
+impl Backing {
+    fn check(backing: &Option<Backing>) -> std::io::Result<&Backing> {
+        backing.as_ref().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "mapping does not own its file"))
+    }
+
+    fn is_stale(&self, map_len: usize) -> std::io::Result<bool> {
+        Ok(self.file.metadata()?.len() != self.off + map_len as u64)
+    }
+}
+
+/// Add the methods of the mappings which may own their file.
+macro_rules! owned_file_methods {
+    ($t:ty) => {
+        impl $t {
+            /// Like `new` but the mapping keeps the file open, which allows
+            /// `sync` to also flush the file data and `is_stale` to check the file.
+            pub fn new_owned<F: Into<Arc<File>>>(addr_hint: AddrHint, conf: MMapConfig, file: F, off: i64) -> std::io::Result<Self> {
+                let file = file.into();
+                let mmap = Self::new(addr_hint, conf, &file, off)?;
+                Ok(Self { backing: Some(Backing { file, off: off as u64 }), ..mmap })
+            }
+
+            /// Return the mapped file if the mapping owns it.
+            pub fn file(&self) -> Option<&File> {
+                self.backing.as_ref().map(|b| &*b.file)
+            }
+
+            /// Check if the mapped file no longer matches the mapping, i.e. it was
+            /// truncated or extended. Fails if the mapping does not own the file.
+            pub fn is_stale(&self) -> std::io::Result<bool> {
+                Backing::check(&self.backing)?.is_stale(self.inner.len())
+            }
+        }
+    };
+}
+
+owned_file_methods!(FileMMap);
+owned_file_methods!(FileMMapMut);
+owned_file_methods!(ExecFileMMap);
+owned_file_methods!(ExecFileMMapMut);
+
+/// How `FileMMapMut::open` opens the file. The lengths are the minimum size
+/// of the file, smaller files are extended with preallocated blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Open an existing file.
+    Existing,
+    /// Open the file, create it if it does not exist.
+    Create(u64),
+    /// Create the file, fail if it exists.
+    CreateNew(u64),
+    /// Create the file or truncate it if it exists.
+    Truncate(u64),
+}
+
+/// Extend `file` to `len` bytes, allocating the blocks with fallocate where
+/// supported so that writes through the mapping do not fail with SIGBUS
+/// when the file system runs full.
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let fd = file.as_raw_fd();
+        if libc::fallocate(fd, 0, 0, len as libc::off_t) == 0 {
+            return Ok(());
+        }
+        match last_errno() {
+            libc::EOPNOTSUPP | libc::ENOSYS | libc::EINVAL => {}
+            errno => return Err(MMapError::Fallocate { errno, fd, mode: 0, offset: 0, len: len as i64 }.into()),
+        }
+    }
+    file.set_len(len)
+}
+
+/// Open an unnamed file for reading and writing in `dir`.
+fn temp_file(dir: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    #[cfg(target_os = "linux")]
+    {
+        let res = OpenOptions::new().read(true).write(true).mode(0o600).custom_flags(libc::O_TMPFILE).open(dir);
+        match res {
+            Ok(file) => return Ok(file),
+            // the file system (or an old kernel) does not support O_TMPFILE.
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EOPNOTSUPP) | Some(libc::EISDIR) | Some(libc::EINVAL)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    loop {
+        let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = dir.join(format!(".mmap-{}-{}.tmp", std::process::id(), n));
+        match OpenOptions::new().read(true).write(true).mode(0o600).create_new(true).open(&path) {
+            Ok(file) => {
+                std::fs::remove_file(&path)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Flush the mapping and, for the blocking sync types, the data of an owned
+/// file with fdatasync.
+fn sync_mapping(inner: &MMapBase, backing: &Option<Backing>, typ: MSyncType) -> std::io::Result<()> {
+    let blocking = matches!(typ, MSyncType::Sync | MSyncType::SyncInvalidate);
+    inner.sync(typ)?;
+    match backing {
+        Some(b) if blocking => b.file.sync_data(),
+        _ => Ok(()),
+    }
+}
+
+/// Populate the pages of the mapping overlapping `range`. On kernels without
+/// MADV_POPULATE only the pages up to the end of an owned file are touched,
+/// see `base::touch_region`.
+fn populate_mapping(inner: &MMapBase, backing: &Option<Backing>, range: Range<usize>, write: bool) -> std::io::Result<()> {
+    let (addr, len) = base::page_range(inner.as_ptr(), inner.len(), range)?;
+    base::populate_region(addr, len, write, || file_extent(inner, backing, addr))
+}
+
+/// Return the part of the region of the mapping starting at `addr` which is
+/// backed by the file, as far as known.
+fn file_extent(inner: &MMapBase, backing: &Option<Backing>, addr: *const u8) -> std::io::Result<Extent> {
+    let Some(b) = backing else {
+        return Ok(Extent::Unknown);
+    };
+    // the mapping starts on a page boundary, so a page aligned region does
+    // not start before it.
+    let skip = addr as u64 - inner.as_ptr() as u64;
+    let backed = b.file.metadata()?.len().saturating_sub(b.off + skip);
+    Ok(Extent::File(backed.try_into().unwrap_or(usize::MAX)))
+}
 
 pub struct FileMMap {
     inner: MMapBase,
+    backing: Option<Backing>,
 }
 
+// SAFETY: see the thread safety notes on `MMap`.
+unsafe impl Send for FileMMap {}
+unsafe impl Sync for FileMMap {}
+
 impl FileMMap {
     pub fn new(addr_hint: AddrHint, conf: MMapConfig, file: &File, off: i64) -> std::io::Result<Self> {
-        let flags = conf.value(); 
-        let prot = Self::prot();
-        let fd = file.as_raw_fd();
         let map_len = file.metadata()?.len() as usize;
 
         // without this check we could get a SIGBUS signal on the mmap call when 
@@ -19,9 +165,56 @@ impl FileMMap {
         if off as usize > map_len {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "offset points beyond file boundary"));
         }
-        let map_len = map_len - off as usize;
-        let inner = MMapBase::new(addr_hint.as_ptr(), map_len, prot, flags, fd, off)?;
-        Ok(Self { inner })
+        Self::new_range(addr_hint, conf, file, off, map_len - off as usize)
+    }
+
+    /// Open the file at `path` read only and map all of it shared. The 
+    /// mapping owns the file, see `new_owned`.
+    pub fn open_readonly<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        Self::new_owned(AddrHint::None, MMapConfig::new().map_shared(), file, 0)
+    }
+
+    /// Map only `len` bytes of the file starting at `off`, which must be a
+    /// multiple of the page size.
+    pub fn new_range(addr_hint: AddrHint, conf: MMapConfig, file: &File, off: i64, len: usize) -> std::io::Result<Self> {
+        let flags = conf.value(); 
+        let prot = Self::prot();
+        let fd = file.as_raw_fd();
+        let file_len = file.metadata()?.len();
+        if off < 0 || off as u64 + len as u64 > file_len {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range points beyond file boundary"));
+        }
+        let inner = MMapBase::new(addr_hint.as_ptr(), len, prot, flags, fd, off)?;
+        Ok(Self { inner, backing: None })
+    }
+
+    /// Like `new_range` but the mapping owns the file, see `new_owned`.
+    pub(crate) fn new_range_owned(addr_hint: AddrHint, conf: MMapConfig, file: Arc<File>, off: i64, len: usize) -> std::io::Result<Self> {
+        let mmap = Self::new_range(addr_hint, conf, &file, off, len)?;
+        Ok(Self { backing: Some(Backing { file, off: off as u64 }), ..mmap })
+    }
+
+    /// Divide the mapped file into at most `n` chunks of roughly equal size
+    /// whose boundaries are moved to the next `delim`, e.g. to process the 
+    /// lines of a log file in parallel.
+    pub fn split_records(&self, n: usize, delim: u8) -> Vec<&[u8]> {
+        records::split_records(self, n, delim)
+    }
+
+    /// Iterate over the lines of the mapped file without copying them.
+    pub fn lines(&self) -> Lines<'_> {
+        Lines::new(self)
+    }
+
+    /// Return the offset of the first occurrence of `needle`.
+    pub fn find(&self, needle: &[u8]) -> Option<usize> {
+        memchr::memmem::find(self, needle)
+    }
+
+    /// Iterate over the offsets of all non-overlapping occurrences of `needle`.
+    pub fn find_all<'n>(&self, needle: &'n [u8]) -> FindAll<'_, 'n> {
+        FindAll::new(self, needle)
     }
 
     #[inline]
@@ -42,11 +235,20 @@ impl MMap for FileMMap {
         self.inner.as_ptr()
     }
     fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
-        self.inner.sync(typ)
+        sync_mapping(&self.inner, &self.backing, typ)
     }
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
         self.inner.advise(config)
     }
+    fn is_shared_file(&self) -> bool {
+        self.inner.is_shared_file()
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, false)
+    }
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.inner.try_read_guarded(range, self.file(), f)
+    }
     fn unmap(self) -> std::io::Result<()> {
         self.inner.unmap()
     }
@@ -55,36 +257,111 @@ impl MMap for FileMMap {
 impl TryFrom<FileMMapMut> for FileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<ExecFileMMap> for FileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<ExecFileMMapMut> for FileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 
 pub struct FileMMapMut {
     inner: MMapBase,
+    backing: Option<Backing>,
 }
 
+// SAFETY: see the thread safety notes on `MMapMut`.
+unsafe impl Send for FileMMapMut {}
+
 impl FileMMapMut {
+    /// Create the file at `path`, or truncate it if it exists, preallocate
+    /// `len` bytes and map it shared. Same as `open(path, OpenMode::Truncate(len))`.
+    pub fn create<P: AsRef<Path>>(path: P, len: u64) -> std::io::Result<Self> {
+        Self::open(path, OpenMode::Truncate(len))
+    }
+
+    /// Open the file at `path` for reading and writing according to `mode`
+    /// and map all of it shared. The mapping owns the file, see `new_owned`.
+    pub fn open<P: AsRef<Path>>(path: P, mode: OpenMode) -> std::io::Result<Self> {
+        let mut opts = OpenOptions::new();
+        opts.read(true).write(true);
+        let len = match mode {
+            OpenMode::Existing => None,
+            OpenMode::Create(len) => {
+                opts.create(true);
+                Some(len)
+            }
+            OpenMode::CreateNew(len) => {
+                opts.create_new(true);
+                Some(len)
+            }
+            OpenMode::Truncate(len) => {
+                opts.create(true).truncate(true);
+                Some(len)
+            }
+        };
+        let file = opts.open(path)?;
+        if let Some(len) = len {
+            if file.metadata()?.len() < len {
+                preallocate(&file, len)?;
+            }
+        }
+        Self::new_owned(AddrHint::None, MMapConfig::new().map_shared(), file, 0)
+    }
+
+    /// Create an unnamed file of `len` bytes in `dir` and map it shared, 
+    /// e.g. to spill data to disk. The file is removed by the file system
+    /// once the mapping is dropped, also if the process crashes. Use
+    /// `link_to` to keep it.
+    ///
+    /// Uses O_TMPFILE where supported, otherwise the file is created under
+    /// a unique name and removed right away.
+    pub fn temp<P: AsRef<Path>>(dir: P, len: u64) -> std::io::Result<Self> {
+        let file = temp_file(dir.as_ref())?;
+        preallocate(&file, len)?;
+        Self::new_owned(AddrHint::None, MMapConfig::new().map_shared(), file, 0)
+    }
+
+    /// Sync the mapping and give the file a name, e.g. to keep a file
+    /// created with `temp` once it is complete.
+    ///
+    /// Only works for files created with O_TMPFILE. Where `temp` has to fall
+    /// back to creating and removing a named file (a file system or kernel
+    /// without O_TMPFILE), the removed file cannot be linked again and this
+    /// always fails with NotFound.
+    #[cfg(target_os = "linux")]
+    pub fn link_to<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+        let b = Backing::check(&self.backing)?;
+        self.sync(MSyncType::Sync)?;
+        let src = std::ffi::CString::new(format!("/proc/self/fd/{}", b.file.as_raw_fd()))?;
+        let dst = std::ffi::CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let rc = unsafe {
+            libc::linkat(libc::AT_FDCWD, src.as_ptr(), libc::AT_FDCWD, dst.as_ptr(), libc::AT_SYMLINK_FOLLOW)
+        };
+        if rc != 0 {
+            return Err(MMapError::Linkat { errno: last_errno(), fd: b.file.as_raw_fd() }.into());
+        }
+        Ok(())
+    }
+
     pub fn new(addr_hint: AddrHint, conf: MMapConfig, file: &File, off: i64) -> std::io::Result<Self> {
         let flags = conf.value(); 
         let prot = Self::prot();
@@ -95,7 +372,20 @@ impl FileMMapMut {
         }
         let map_len = map_len - off as usize;
         let inner = MMapBase::new(addr_hint.as_ptr(), map_len, prot, flags, fd, off)?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: None })
+    }
+
+    /// Set what dropping or unmapping the mapping does before it is 
+    /// unmapped. `DropPolicy::SyncData` fails if the mapping does not own
+    /// its file.
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> std::io::Result<()> {
+        let file = self.backing.as_ref().map(|b| b.file.clone());
+        self.inner.set_drop_policy(policy, file)
+    }
+
+    /// Return the drop policy, `DropPolicy::None` unless set.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.inner.drop_policy()
     }
 
     #[inline]
@@ -124,8 +414,17 @@ impl MMap for FileMMapMut {
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
         self.inner.advise(config)
     }
+    fn is_shared_file(&self) -> bool {
+        self.inner.is_shared_file()
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, false)
+    }
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.inner.try_read_guarded(range, self.file(), f)
+    }
     fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
-        self.inner.sync(typ)
+        sync_mapping(&self.inner, &self.backing, typ)
     }
     fn unmap(self) -> std::io::Result<()> {
         self.inner.unmap()
@@ -136,32 +435,61 @@ impl MMapMut for FileMMapMut {
     fn as_mut_ptr(&mut self) -> *mut u8 {
         self.inner.as_mut_ptr()
     }
+    fn populate_write(&mut self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, true)
+    }
+}
+
+impl MMapResize for FileMMapMut {
+    /// Resize the mapping and the file, which is truncated or extended to
+    /// end with the mapping. Fails if the mapping does not own the file or
+    /// is private, whose changes are not meant to reach the file.
+    fn resize(&mut self, new_len: usize) -> std::io::Result<()> {
+        let b = Backing::check(&self.backing)?;
+        if !self.inner.is_shared_file() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "resizing a private mapping would change the file"));
+        }
+        let end = b.off + new_len as u64;
+        if new_len > self.inner.len() {
+            let old_end = b.file.metadata()?.len();
+            b.file.set_len(end)?;
+            if let Err(e) = self.inner.remap(new_len) {
+                // the mapping is unchanged, so should be the file.
+                let _ = b.file.set_len(old_end);
+                return Err(e);
+            }
+            Ok(())
+        } else {
+            self.inner.remap(new_len)?;
+            b.file.set_len(end)
+        }
+    }
 }
 
 impl TryFrom<FileMMap> for FileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<ExecFileMMap> for FileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<ExecFileMMapMut> for FileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
@@ -169,8 +497,13 @@ impl TryFrom<ExecFileMMapMut> for FileMMapMut {
 
 pub struct ExecFileMMap {
     inner: MMapBase,
+    backing: Option<Backing>,
 }
 
+// SAFETY: see the thread safety notes on `MMap`.
+unsafe impl Send for ExecFileMMap {}
+unsafe impl Sync for ExecFileMMap {}
+
 impl ExecFileMMap {
     pub fn new(addr_hint: AddrHint, conf: MMapConfig, file: &File, off: i64) -> std::io::Result<Self> {
         let flags = conf.value(); 
@@ -182,7 +515,7 @@ impl ExecFileMMap {
         }
         let map_len = map_len - off as usize;
         let inner = MMapBase::new(addr_hint.as_ptr(), map_len, prot, flags, fd, off)?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: None })
     }
 
     #[inline]
@@ -205,8 +538,17 @@ impl MMap for ExecFileMMap {
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
         self.inner.advise(config)
     }
+    fn is_shared_file(&self) -> bool {
+        self.inner.is_shared_file()
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, false)
+    }
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.inner.try_read_guarded(range, self.file(), f)
+    }
     fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
-        self.inner.sync(typ)
+        sync_mapping(&self.inner, &self.backing, typ)
     }
     fn unmap(self) -> std::io::Result<()> {
         self.inner.unmap()
@@ -216,27 +558,27 @@ impl MMap for ExecFileMMap {
 impl TryFrom<ExecFileMMapMut> for ExecFileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<FileMMapMut> for ExecFileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<FileMMap> for ExecFileMMap {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
@@ -244,8 +586,12 @@ impl TryFrom<FileMMap> for ExecFileMMap {
 
 pub struct ExecFileMMapMut {
     inner: MMapBase,
+    backing: Option<Backing>,
 }
 
+// SAFETY: see the thread safety notes on `MMapMut`.
+unsafe impl Send for ExecFileMMapMut {}
+
 impl ExecFileMMapMut {
     pub fn new(addr_hint: AddrHint, conf: MMapConfig, file: &File, off: i64) -> std::io::Result<Self> {
         let flags = conf.value(); 
@@ -257,7 +603,20 @@ impl ExecFileMMapMut {
         }
         let map_len = map_len - off as usize;
         let inner = MMapBase::new(addr_hint.as_ptr(), map_len, prot, flags, fd, off)?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: None })
+    }
+
+    /// Set what dropping or unmapping the mapping does before it is 
+    /// unmapped. `DropPolicy::SyncData` fails if the mapping does not own
+    /// its file.
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> std::io::Result<()> {
+        let file = self.backing.as_ref().map(|b| b.file.clone());
+        self.inner.set_drop_policy(policy, file)
+    }
+
+    /// Return the drop policy, `DropPolicy::None` unless set.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.inner.drop_policy()
     }
 
     #[inline]
@@ -286,8 +645,17 @@ impl MMap for ExecFileMMapMut {
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
         self.inner.advise(config)
     }
+    fn is_shared_file(&self) -> bool {
+        self.inner.is_shared_file()
+    }
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, false)
+    }
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.inner.try_read_guarded(range, self.file(), f)
+    }
     fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
-        self.inner.sync(typ)
+        sync_mapping(&self.inner, &self.backing, typ)
     }
     fn unmap(self) -> std::io::Result<()> {
         self.inner.unmap()
@@ -298,32 +666,35 @@ impl MMapMut for ExecFileMMapMut {
     fn as_mut_ptr(&mut self) -> *mut u8 {
         self.inner.as_mut_ptr()
     }
+    fn populate_write(&mut self, range: Range<usize>) -> std::io::Result<()> {
+        populate_mapping(&self.inner, &self.backing, range, true)
+    }
 }
 
 impl TryFrom<ExecFileMMap> for ExecFileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: ExecFileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<FileMMapMut> for ExecFileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMapMut) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
 impl TryFrom<FileMMap> for ExecFileMMapMut {
     type Error = std::io::Error;
     fn try_from(mmap: FileMMap) -> Result<Self, Self::Error> {
-        let inner = mmap.inner;
+        let mut inner = mmap.inner;
         inner.protect(Self::prot())?;
-        Ok(Self { inner })
+        Ok(Self { inner, backing: mmap.backing })
     }
 }
 
@@ -347,11 +718,12 @@ mod tests {
                         .read(true)
                         .write(true)
                         .create(true)
+                        .truncate(true)
                         .open(fpath).unwrap();
 
             let mut buf = vec![0u8; cnt];
             rand::thread_rng().fill(&mut buf[..]);
-            fp.write(&buf)?;
+            fp.write_all(&buf)?;
             Ok(Self { path: String::from(fpath), fp })
         }
 
@@ -421,8 +793,8 @@ mod tests {
         // now check file via normal file api.
         let mut freadbuf: Vec<u8> = Vec::new();
         tf.fp.read_to_end(&mut freadbuf).unwrap();
-        for i in 0..freadbuf.len() {
-            assert_eq!(freadbuf[i], 0xff);
+        for b in freadbuf.iter() {
+            assert_eq!(*b, 0xff);
         }
     }
     
@@ -578,10 +950,24 @@ mod tests {
         let res = tf.spawn_mmap(off);
         assert!(res.is_err());
         if let Err(e) = res {
-            assert_eq!(e.raw_os_error().unwrap(), libc::EINVAL);
+            // raw_os_error is None for the errors of this crate.
+            assert_eq!(crate::MMapError::from_io(&e).unwrap().errno(), libc::EINVAL);
         }
     }
 
+    #[test]
+    fn mmap_error_names_the_syscall() {
+        let off = crate::base::get_page_size() + 1;
+        let tf = crate::testutil::TestFile::pattern(10000);
+        let e = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, off).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(e.raw_os_error(), None);
+        let err = e.get_ref().and_then(|e| e.downcast_ref::<crate::MMapError>()).unwrap();
+        assert_eq!(err.syscall(), "mmap");
+        assert!(matches!(err, crate::MMapError::Mmap { offset, .. } if *offset == off));
+        assert!(e.to_string().contains(&format!("offset={}", off)), "{}", e);
+    }
+
     #[test]
     fn conversions_from_file_mmap() {
         let cnt = 10;
@@ -641,4 +1027,290 @@ mod tests {
         let mmap = tf.spawn_exec_mmap_mut(0).unwrap();
         let _: ExecFileMMap = mmap.try_into().unwrap();
     }
+
+    #[test]
+    fn concurrent_readers_share_file_mmap() {
+        let cnt = 100000;
+        let tf = crate::testutil::TestFile::pattern(cnt);
+        let expected = crate::testutil::pattern(0..cnt);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        let mmap = std::sync::Arc::new(mmap);
+        let handles = (0..4).map(|t| {
+            let mmap = mmap.clone();
+            std::thread::spawn(move || {
+                let chunk = mmap.len() / 4;
+                mmap[t * chunk..(t + 1) * chunk].to_vec()
+            })
+        }).collect::<Vec<_>>();
+        let chunk = cnt / 4;
+        for (t, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), expected[t * chunk..(t + 1) * chunk]);
+        }
+    }
+
+    #[test]
+    fn move_file_mmap_mut_to_worker() {
+        let cnt = 100000;
+        let tf = crate::testutil::TestFile::pattern(cnt);
+        let mut mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        let mmap = std::thread::spawn(move || {
+            mmap.fill(0xab);
+            mmap
+        }).join().unwrap();
+        assert!(mmap.iter().all(|b| *b == 0xab));
+        // private mapping, the file itself stays untouched.
+        assert_eq!(std::fs::read(&tf.path).unwrap(), crate::testutil::pattern(0..cnt));
+    }
+
+    #[test]
+    fn lines_and_records_of_file() {
+        let text = (0..1000).map(|i| format!("line {}\n", i)).collect::<String>();
+        let tf = crate::testutil::TestFile::new(text.as_bytes());
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+
+        let lines = mmap.lines().utf8().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines, text.lines().collect::<Vec<_>>());
+
+        let chunks = mmap.split_records(7, b'\n');
+        assert!(chunks.len() <= 7);
+        let nlines = chunks.iter().map(|c| c.iter().filter(|b| **b == b'\n').count()).sum::<usize>();
+        assert_eq!(nlines, 1000);
+
+        assert_eq!(mmap.find(b"line 10\n"), text.find("line 10\n"));
+        assert_eq!(mmap.find_all(b"line 99").count(), 11);
+    }
+
+    #[test]
+    fn populate_file_mmap() {
+        let cnt = 100000;
+        let tf = crate::testutil::TestFile::pattern(cnt);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        mmap.populate_read(0..cnt).unwrap();
+        mmap.populate_read(5000..5001).unwrap();
+        let info = crate::IncoreInfo::read(&mmap).unwrap();
+        assert_eq!(info.resident_count(), info.flagvec_len());
+        let e = mmap.populate_read(0..cnt + 1).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+
+        let mut mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        mmap.populate_write(100..cnt).unwrap();
+        mmap[100] = 1;
+        assert_eq!(mmap[100], 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn populate_advice_on_file_mmap() {
+        let cnt = 100000;
+        let tf = crate::testutil::TestFile::pattern(cnt);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        mmap.advise_range(0..cnt, MAdviseConfig::new().madv_populate_read()).unwrap();
+        let info = crate::IncoreInfo::read(&mmap).unwrap();
+        assert_eq!(info.resident_count(), info.flagvec_len());
+
+        let mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        mmap.advise_range(0..cnt, MAdviseConfig::new().madv_populate_write()).unwrap();
+        mmap.advise(MAdviseConfig::new().madv_populate_read()).unwrap();
+        assert_eq!(mmap[..], crate::testutil::pattern(0..cnt)[..]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn populate_truncated_file() {
+        let cnt = 100000;
+        let tf = crate::testutil::TestFile::pattern(cnt);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        tf.fp.set_len(10000).unwrap();
+        mmap.populate_read(0..10000).unwrap();
+        let e = mmap.populate_read(0..cnt).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn touching_stops_at_end_of_file() {
+        // what populate_read falls back to on kernels without MADV_POPULATE.
+        let ps = crate::base::get_page_size() as usize;
+        let tf = crate::testutil::TestFile::pattern(4 * ps);
+        let mmap = FileMMap::new_owned(AddrHint::None, MMapConfig::new().map_shared(), tf.fp.try_clone().unwrap(), 0).unwrap();
+        tf.fp.set_len(ps as u64 + 1).unwrap();
+        let addr = mmap.as_ptr() as *mut u8;
+        let extent = file_extent(&mmap.inner, &mmap.backing, addr).unwrap();
+        let e = base::touch_region(addr, 4 * ps, false, extent).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        let extent = file_extent(&mmap.inner, &mmap.backing, addr).unwrap();
+        base::touch_region(addr, 2 * ps, false, extent).unwrap();
+        assert_eq!(mmap[ps], crate::testutil::pattern(ps..ps + 1)[0]);
+
+        let borrowed = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        assert!(matches!(file_extent(&borrowed.inner, &borrowed.backing, borrowed.as_ptr()).unwrap(), Extent::Unknown));
+    }
+
+    #[test]
+    fn owned_file_sync_and_staleness() {
+        let cnt = 10000;
+        let tf = crate::testutil::TestFile::new(&vec![0; cnt]);
+        let fp = tf.fp.try_clone().unwrap();
+        let mut mmap = FileMMapMut::new_owned(AddrHint::None, MMapConfig::new().map_shared(), fp, 0).unwrap();
+        assert!(mmap.file().is_some());
+        mmap[..4].copy_from_slice(b"abcd");
+        mmap.sync(MSyncType::Sync).unwrap();
+        assert!(!mmap.is_stale().unwrap());
+
+        // the owned file survives the conversion.
+        let mmap: FileMMap = mmap.try_into().unwrap();
+        assert_eq!(mmap.file().unwrap().metadata().unwrap().len(), cnt as u64);
+        tf.fp.set_len(cnt as u64 + 1).unwrap();
+        assert!(mmap.is_stale().unwrap());
+
+        let borrowed = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        let e = borrowed.is_stale().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resize_owned_file() {
+        let cnt = 100;
+        let tf = crate::testutil::TestFile::new(&vec![0; cnt]);
+        let fp = Arc::new(tf.fp.try_clone().unwrap());
+        let mut mmap = FileMMapMut::new_owned(AddrHint::None, MMapConfig::new().map_shared(), fp.clone(), 0).unwrap();
+        {
+            let mut writer = crate::MMapWriter::extending(&mut mmap);
+            writer.seek(SeekFrom::End(0)).unwrap();
+            writer.write_all(&[0xaa; 5000]).unwrap();
+        }
+        assert!(mmap.len() >= cnt + 5000);
+        assert_eq!(fp.metadata().unwrap().len(), mmap.len() as u64);
+        mmap.resize(cnt + 10).unwrap();
+        mmap.sync(MSyncType::Sync).unwrap();
+        let data = std::fs::read(&tf.path).unwrap();
+        assert_eq!(data.len(), cnt + 10);
+        assert_eq!(data[cnt..], [0xaa; 10]);
+
+        let mut mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        assert_eq!(mmap.resize(10).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+
+        let mut mmap = FileMMapMut::new_owned(AddrHint::None, MMapConfig::new().map_private(), fp.clone(), 0).unwrap();
+        assert_eq!(mmap.resize(10).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(fp.metadata().unwrap().len(), cnt as u64 + 10);
+    }
+
+    #[test]
+    fn create_and_open_by_path() {
+        let tf = crate::testutil::TestFile::new(b"");
+        let path = &tf.path;
+        std::fs::remove_file(path).unwrap();
+        let e = FileMMapMut::open(path, OpenMode::Existing).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+
+        let mut mmap = FileMMapMut::create(path, 10000).unwrap();
+        assert_eq!(mmap.len(), 10000);
+        mmap[9999] = 7;
+        mmap.sync(MSyncType::Sync).unwrap();
+        drop(mmap);
+
+        let e = FileMMapMut::open(path, OpenMode::CreateNew(10)).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+        // an existing file is only grown, never shrunk.
+        let mmap = FileMMapMut::open(path, OpenMode::Create(100)).unwrap();
+        assert_eq!(mmap.len(), 10000);
+        assert_eq!(mmap[9999], 7);
+        drop(mmap);
+        let mmap = FileMMapMut::open(path, OpenMode::Create(20000)).unwrap();
+        assert_eq!(mmap.len(), 20000);
+        assert_eq!(mmap[9999], 7);
+        drop(mmap);
+
+        let mmap = FileMMap::open_readonly(path).unwrap();
+        assert_eq!(mmap.len(), 20000);
+        assert!(mmap.file().is_some());
+        assert!(FileMMapMut::try_from(mmap).is_err());
+
+        let mmap = FileMMapMut::create(path, 50).unwrap();
+        assert!(mmap.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn map_empty_file() {
+        let tf = crate::testutil::TestFile::new(b"");
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_private(), &tf.fp, 0).unwrap();
+        assert!(mmap.is_empty());
+        mmap.advise(MAdviseConfig::new().madv_sequential()).unwrap();
+        assert_eq!(mmap.lines().count(), 0);
+        let mmap: FileMMapMut = mmap.try_into().unwrap();
+        mmap.sync(MSyncType::Sync).unwrap();
+        mmap.unmap().unwrap();
+
+        let mmap = FileMMapMut::create(&tf.path, 0).unwrap();
+        assert!(mmap.is_empty());
+        assert!(!mmap.is_stale().unwrap());
+        let exec = ExecFileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        assert!(exec.is_empty());
+        let view = crate::MappedBytes::new(FileMMap::open_readonly(&tf.path).unwrap());
+        assert!(view.is_empty());
+    }
+
+    #[test]
+    fn temp_file_mapping() {
+        let dir = std::env::temp_dir();
+        let mut mmap = FileMMapMut::temp(&dir, 10000).unwrap();
+        assert_eq!(mmap.len(), 10000);
+        assert_eq!(mmap.file().unwrap().metadata().unwrap().len(), 10000);
+        mmap.fill(0x3c);
+        #[cfg(target_os = "linux")]
+        {
+            mmap.resize(20000).unwrap();
+            assert_eq!(mmap[9999..10001], [0x3c, 0]);
+        }
+        assert!(FileMMapMut::temp(dir.join("does-not-exist-k2v9q"), 10).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn link_temp_file() {
+        use std::os::unix::fs::OpenOptionsExt;
+        let dir = std::env::temp_dir();
+        let probe = OpenOptions::new().read(true).write(true).mode(0o600).custom_flags(libc::O_TMPFILE).open(&dir);
+        if let Err(e) = probe {
+            // without O_TMPFILE `link_to` cannot work, see its docs.
+            assert!(matches!(e.raw_os_error(), Some(libc::EOPNOTSUPP) | Some(libc::EISDIR) | Some(libc::EINVAL)), "{}", e);
+            return;
+        }
+        // the target is removed again when `tf` is dropped.
+        let tf = crate::testutil::TestFile::new(b"");
+        let path = tf.path.clone();
+        std::fs::remove_file(&path).unwrap();
+        let mut mmap = FileMMapMut::temp(&dir, 100).unwrap();
+        mmap.fill(0x11);
+        mmap.link_to(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0x11; 100]);
+        let e = mmap.link_to(&path).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn drop_policy() {
+        let tf = crate::testutil::TestFile::new(&[0; 10]);
+        let path = &tf.path;
+        let mut mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        assert_eq!(mmap.drop_policy(), DropPolicy::None);
+        let e = mmap.set_drop_policy(DropPolicy::SyncData).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+        mmap.set_drop_policy(DropPolicy::Sync).unwrap();
+        mmap.fill(b'a');
+        drop(mmap);
+
+        let mut mmap = FileMMapMut::open(path, OpenMode::Existing).unwrap();
+        assert_eq!(&mmap[..], b"aaaaaaaaaa");
+        mmap.set_drop_policy(DropPolicy::SyncData).unwrap();
+        // the policy is kept when the region is replaced.
+        mmap.resize(0).unwrap();
+        mmap.resize(4).unwrap();
+        assert_eq!(mmap.drop_policy(), DropPolicy::SyncData);
+        mmap.copy_from_slice(b"bbbb");
+        let mmap: ExecFileMMapMut = mmap.try_into().unwrap();
+        assert_eq!(mmap.drop_policy(), DropPolicy::SyncData);
+        mmap.unmap().unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"bbbb");
+    }
 }
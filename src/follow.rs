@@ -0,0 +1,184 @@
+use crate::{AddrHint, FileMMap, MMap, MMapConfig};
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Read only mapping of a file that other processes append to, e.g. to
+/// tail a log file.
+///
+/// The length of the file is checked by `refresh` (and while waiting in
+/// `wait_for_len`). Whenever the length has changed the file is mapped
+/// again, to cover the new bytes or to drop the pages beyond the new end.
+///
+/// A truncation by another process between two refreshes is not noticed,
+/// accessing the removed pages raises SIGBUS. Use `try_read` to access data
+/// which may be truncated concurrently.
+pub struct FollowingFileMap {
+    file: Arc<File>,
+    mmap: Option<FileMMap>,
+    len: usize,
+    poll_interval: Duration,
+}
+
+impl FollowingFileMap {
+    /// Open the file at `path` read only and map it.
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    pub fn new(file: File) -> std::io::Result<Self> {
+        let mut map = Self { file: Arc::new(file), mmap: None, len: 0, poll_interval: DEFAULT_POLL_INTERVAL };
+        map.refresh()?;
+        Ok(map)
+    }
+
+    /// Set how often `wait_for_len` checks the length of the file.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Return the length of the file at the last refresh.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Return the contents of the file as of the last refresh.
+    pub fn data(&self) -> &[u8] {
+        match &self.mmap {
+            Some(mmap) => &mmap[..self.len],
+            None => &[],
+        }
+    }
+
+    /// Return the bytes at `range`, fails with UnexpectedEof if the range
+    /// extends beyond the end of the file as of the last refresh.
+    pub fn get(&self, range: Range<usize>) -> std::io::Result<&[u8]> {
+        if range.start > range.end || range.end > self.len {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "range is beyond the end of the file"));
+        }
+        Ok(&self.data()[range])
+    }
+
+    /// Like `get` but passes the bytes to `f` and also reports a truncation
+    /// since the last refresh as UnexpectedEof, see `MMap::try_read`.
+    pub fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> {
+        self.get(range.clone())?;
+        match &self.mmap {
+            Some(mmap) => mmap.try_read(range, f),
+            None => Ok(f(&[])),
+        }
+    }
+
+    /// Check the length of the file and map it again if it has changed.
+    /// Returns true if the length has changed.
+    pub fn refresh(&mut self) -> std::io::Result<bool> {
+        let len = self.file.metadata()?.len() as usize;
+        if len == self.len {
+            return Ok(false);
+        }
+        // a file that shrank is mapped again as well, so that the mapping
+        // never covers pages beyond the end of the file once it regrows.
+        let mmap = FileMMap::new_range_owned(AddrHint::None, MMapConfig::new().map_shared(), self.file.clone(), 0, len)?;
+        self.mmap = Some(mmap);
+        self.len = len;
+        Ok(true)
+    }
+
+    /// Block until the file is at least `len` bytes long or `timeout` has
+    /// passed (never, if None). Returns true if the length was reached.
+    pub fn wait_for_len(&mut self, len: usize, timeout: Option<Duration>) -> std::io::Result<bool> {
+        let start = Instant::now();
+        loop {
+            self.refresh()?;
+            if self.len >= len {
+                return Ok(true);
+            }
+            let mut sleep = self.poll_interval;
+            if let Some(timeout) = timeout {
+                let elapsed = start.elapsed();
+                if elapsed >= timeout {
+                    return Ok(false);
+                }
+                sleep = std::cmp::min(sleep, timeout - elapsed);
+            }
+            std::thread::sleep(sleep);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestFile;
+
+    #[test]
+    fn follow_growth_and_truncation() {
+        let tf = TestFile::new(b"first\n");
+        let mut map = FollowingFileMap::open(&tf.path).unwrap();
+        assert_eq!(map.data(), b"first\n");
+        assert!(!map.refresh().unwrap());
+
+        tf.append(b"second\n");
+        assert_eq!(map.len(), 6);
+        assert!(map.refresh().unwrap());
+        assert_eq!(map.data(), b"first\nsecond\n");
+        assert_eq!(map.get(6..12).unwrap(), b"second");
+
+        tf.fp.set_len(3).unwrap();
+        assert!(map.refresh().unwrap());
+        assert_eq!(map.data(), b"fir");
+        let e = map.get(0..4).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(map.try_read(0..3, |s| s.to_vec()).unwrap(), b"fir");
+    }
+
+    #[test]
+    fn shrink_and_regrow() {
+        let ps = crate::base::get_page_size() as usize;
+        let tf = TestFile::new(&vec![b'a'; 3 * ps]);
+        let mut map = FollowingFileMap::open(&tf.path).unwrap();
+        tf.fp.set_len(ps as u64).unwrap();
+        assert!(map.refresh().unwrap());
+        tf.append(&vec![b'b'; 2 * ps]);
+        assert!(map.refresh().unwrap());
+        assert_eq!(map.get(2 * ps..2 * ps + 1).unwrap(), b"b");
+
+        // a truncation that is only noticed by a fault.
+        tf.fp.set_len(ps as u64).unwrap();
+        let e = map.try_read(2 * ps..3 * ps, |s| s[0]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        tf.append(&vec![b'c'; 2 * ps]);
+        assert!(!map.refresh().unwrap());
+        assert_eq!(map.get(2 * ps..2 * ps + 1).unwrap(), b"c");
+    }
+
+    #[test]
+    fn empty_file_and_wait() {
+        let tf = TestFile::new(b"");
+        let mut map = FollowingFileMap::open(&tf.path).unwrap();
+        map.set_poll_interval(Duration::from_millis(5));
+        assert!(map.is_empty());
+        assert!(!map.wait_for_len(1, Some(Duration::from_millis(20))).unwrap());
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                tf.append(b"hello");
+            });
+            assert!(map.wait_for_len(5, None).unwrap());
+        });
+        assert_eq!(map.data(), b"hello");
+    }
+}
@@ -1,55 +1,159 @@
-use std::io::{Read, Write, Seek, SeekFrom};
-use crate::{MMap, MMapMut};
+use std::io::{BufRead, IoSlice, IoSliceMut, Read, Write, Seek, SeekFrom};
+use std::fs::File;
+use std::sync::Arc;
+use crate::{MMap, MMapMut, MMapResize, MAdviseConfig};
+
+/// Compute the cursor position after seeking to `pos` in a stream of `len`
+/// bytes. Seeking past the end is allowed, seeking before byte 0 is not.
+pub(crate) fn seek_position(cur: u64, len: usize, pos: SeekFrom) -> std::io::Result<u64> {
+    let c = match pos {
+        SeekFrom::Start(n) => return Ok(n),
+        SeekFrom::End(n) => len as i64 + n,
+        SeekFrom::Current(n) => cur as i64 + n,
+    };
+    if c < 0 {
+        let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before byte 0");
+        return Err(e);
+    }
+    Ok(c as u64)
+}
 
 pub struct MMapReader<'a, M: MMap> {
     cur: u64,
     mmap: &'a M,
+    stream: Option<Streaming<'a>>,
+}
+
+/// State of a reader in streaming mode, see `MMapReader::streaming`.
+struct Streaming<'a> {
+    window: usize,
+    /// Pages up to here have been advised with MADV_WILLNEED.
+    ahead: usize,
+    /// Pages below this offset have been released.
+    behind: usize,
+    /// File and offset of the mapping, to drop pages from the page cache.
+    file: Option<(&'a File, u64)>,
 }
 
 impl<'a, M: MMap> MMapReader<'a, M> {
     pub fn new(mmap: &'a M) -> Self {
-        Self { cur: 0, mmap }
+        Self { cur: 0, mmap, stream: None }
     }
 
-    fn seek_from_start(&mut self, pos: u64) -> std::io::Result<u64> {
-        self.cur = pos;
-        Ok(self.cur)
+    /// Create a reader for a single sequential pass over a mapping that may
+    /// be much larger than the available memory. The mapping is advised 
+    /// with MADV_SEQUENTIAL, the next `window` bytes behind the cursor are 
+    /// advised with MADV_WILLNEED and, for a shared file mapping, pages 
+    /// the cursor has passed are released with MADV_DONTNEED. 
+    ///
+    /// Pages of other mappings are never released: their contents would
+    /// be lost while slices returned by `peek`, `take_slice` or `fill_buf`
+    /// still point to them, see `MMap::is_shared_file`.
+    pub fn streaming(mmap: &'a M, window: usize) -> std::io::Result<Self> {
+        let ps = crate::base::get_page_size() as usize;
+        let window = std::cmp::max(window.div_ceil(ps), 1) * ps;
+        if !mmap.is_empty() {
+            mmap.advise(MAdviseConfig::new().madv_sequential())?;
+        }
+        let stream = Streaming { window, ahead: 0, behind: 0, file: None };
+        let mut reader = Self { cur: 0, mmap, stream: Some(stream) };
+        reader.advise_stream()?;
+        Ok(reader)
     }
-    
-    fn seek_from_end(&mut self, pos: i64) -> std::io::Result<u64> {
-        let c = self.mmap.len() as i64 + pos;
-        if c < 0 {
-            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before byte 0");
-            return Err(e);
+
+    /// Also drop released pages from the page cache with 
+    /// POSIX_FADV_DONTNEED, so a large scan does not evict the working set
+    /// of other readers. `file` must be the file the mapping was created 
+    /// from at `offset`. Has no effect if the reader is not streaming.
+    pub fn release_page_cache(mut self, file: &'a File, offset: u64) -> Self {
+        if let Some(s) = self.stream.as_mut() {
+            s.file = Some((file, offset));
         }
-        self.cur = c as u64;
-        Ok(self.cur)
+        self
     }
-    
-    fn seek_from_current(&mut self, pos: i64) -> std::io::Result<u64> {
-        let c = self.cur as i64 + pos;
-        if c < 0 {
-            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before byte 0");
-            return Err(e);
+
+    /// Issue read ahead and release advice after the cursor has moved.
+    fn advise_stream(&mut self) -> std::io::Result<()> {
+        let Some(s) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        let len = self.mmap.len();
+        let cur = std::cmp::min(self.cur as usize, len);
+        let ps = crate::base::get_page_size() as usize;
+        if cur < s.behind || cur < s.ahead.saturating_sub(s.window) {
+            // the cursor moved backwards, start over from here.
+            s.behind = cur - cur % ps;
+            s.ahead = cur;
         }
-        self.cur = c as u64;
-        Ok(self.cur)
+
+        // advise in steps of half a window to keep the number of syscalls low.
+        let ahead = std::cmp::min(cur + s.window, len);
+        if ahead > s.ahead && (ahead - s.ahead >= s.window / 2 || ahead == len) {
+            self.mmap.advise_range(s.ahead..ahead, MAdviseConfig::new().madv_willneed())?;
+            s.ahead = ahead;
+        }
+        let behind = cur - cur % ps;
+        let release = self.mmap.is_shared_file();
+        if release && behind > s.behind && (behind - s.behind >= s.window / 2 || cur == len) {
+            self.mmap.advise_range(s.behind..behind, MAdviseConfig::new().madv_dontneed())?;
+            #[cfg(not(target_os = "macos"))]
+            if let Some((file, off)) = s.file {
+                use std::os::unix::prelude::AsRawFd;
+                use crate::error::MMapError;
+                let (fd, offset, len) = (file.as_raw_fd(), (off + s.behind as u64) as i64, (behind - s.behind) as i64);
+                let advice = libc::POSIX_FADV_DONTNEED;
+                // returns the errno instead of setting it.
+                let rc = unsafe { libc::posix_fadvise(fd, offset as libc::off_t, len as libc::off_t, advice) };
+                if rc != 0 {
+                    return Err(MMapError::Fadvise { errno: rc, fd, offset, len, advice }.into());
+                }
+            }
+            s.behind = behind;
+        }
+        Ok(())
+    }
+
+    fn at(mut self, cur: u64) -> Self {
+        self.cur = cur;
+        self
+    }
+
+    /// Return the bytes between the cursor and the end of the mapping.
+    fn remaining(&self) -> &'a [u8] {
+        let mmap: &'a [u8] = self.mmap;
+        let cur = std::cmp::min(self.cur, mmap.len() as u64) as usize;
+        &mmap[cur..]
+    }
+
+    /// Return up to `n` bytes behind the cursor without advancing it. The 
+    /// slice borrows the mapping, not the reader.
+    pub fn peek(&self, n: usize) -> &'a [u8] {
+        let rem = self.remaining();
+        &rem[..std::cmp::min(n, rem.len())]
+    }
+
+    /// Return up to `n` bytes behind the cursor and advance the cursor past
+    /// them. The slice borrows the mapping, not the reader.
+    pub fn take_slice(&mut self, n: usize) -> &'a [u8] {
+        let s = self.peek(n);
+        self.cur += s.len() as u64;
+        // advice is only a hint, a failure must not lose the slice.
+        let _ = self.advise_stream();
+        s
     }
 }
 
 impl<'a, M: MMap> Seek for MMapReader<'a, M> {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        match pos {
-            SeekFrom::Current(n) => self.seek_from_current(n),
-            SeekFrom::Start(n) => self.seek_from_start(n),
-            SeekFrom::End(n) => self.seek_from_end(n),
-        }
+        self.cur = seek_position(self.cur, self.mmap.len(), pos)?;
+        self.advise_stream()?;
+        Ok(self.cur)
     }
 }
 
 impl<'a, M: MMap> Read for MMapReader<'a, M> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
         if self.cur as usize >= self.mmap.len() {
@@ -61,78 +165,279 @@ impl<'a, M: MMap> Read for MMapReader<'a, M> {
         let n = top - self.cur as usize;
         buf[0..n].copy_from_slice(&self.mmap[self.cur as usize..top]);
         self.cur = top as u64;
+        self.advise_stream()?;
         Ok(n) 
     }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        let rem = self.remaining();
+        if rem.len() < buf.len() {
+            let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Not enough bytes left in mapping");
+            return Err(e);
+        }
+        buf.copy_from_slice(&rem[..buf.len()]);
+        self.cur += buf.len() as u64;
+        self.advise_stream()
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
+        let mut n = 0;
+        for buf in bufs {
+            let s = self.take_slice(buf.len());
+            buf[..s.len()].copy_from_slice(s);
+            n += s.len();
+            if s.len() < buf.len() {
+                break;
+            }
+        }
+        Ok(n)
+    }
 }
 
+impl<'a, M: MMap> BufRead for MMapReader<'a, M> {
+    /// Returns the rest of the mapping, no bytes are copied. In streaming
+    /// mode at most one window is returned.
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        match &self.stream {
+            Some(s) => Ok(self.peek(s.window)),
+            None => Ok(self.remaining()),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cur += amt as u64;
+        let _ = self.advise_stream();
+    }
+}
+
+/// Writes to a mapping at the position of the cursor. 
+///
+/// A writer created with `new` cannot write past the end of the mapping: 
+/// a write that does not fit is shortened and writing at or beyond the end
+/// fails with `ErrorKind::WriteZero`. A writer created with `extending` 
+/// instead grows the mapping (and its file, if any) to make room.
 pub struct MMapWriter<'a, M: MMapMut> {
     cur: u64,
     mmap: &'a mut M,
+    grow: Option<fn(&mut M, usize) -> std::io::Result<()>>,
 }
 
 impl<'a, M:MMapMut> MMapWriter<'a, M> {
     pub fn new(mmap: &'a mut M) -> Self {
-        Self { cur: 0, mmap }
-    }
-    
-    fn seek_from_start(&mut self, pos: u64) -> std::io::Result<u64> {
-        self.cur = pos;
-        Ok(self.cur)
+        Self { cur: 0, mmap, grow: None }
     }
-    
-    fn seek_from_end(&mut self, pos: i64) -> std::io::Result<u64> {
-        let c = self.mmap.len() as i64 + pos;
-        if c < 0 {
-            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before byte 0");
-            return Err(e);
+
+    /// Make sure the mapping is at least `end` bytes long, if the writer 
+    /// is allowed to grow it.
+    fn reserve(&mut self, end: usize) -> std::io::Result<()> {
+        if end <= self.mmap.len() {
+            return Ok(());
         }
-        self.cur = c as u64;
-        Ok(self.cur)
-    }
-    
-    fn seek_from_current(&mut self, pos: i64) -> std::io::Result<u64> {
-        let c = self.cur as i64 + pos;
-        if c < 0 {
-            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cannot seek before byte 0");
-            return Err(e);
+        match self.grow {
+            Some(grow) => grow(self.mmap, end),
+            None => Ok(()),
         }
-        self.cur = c as u64;
-        Ok(self.cur)
+    }
+
+    /// Return the position after writing `len` bytes at the cursor.
+    fn end_of_write(&self, len: usize) -> std::io::Result<usize> {
+        usize::try_from(self.cur).ok().and_then(|cur| cur.checked_add(len)).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "write would end beyond the maximum offset")
+        })
+    }
+}
+
+impl<'a, M: MMapResize> MMapWriter<'a, M> {
+    /// Create a writer which resizes the mapping when a write goes past its
+    /// end. Bytes between the old end and the cursor are zero filled.
+    pub fn extending(mmap: &'a mut M) -> Self {
+        Self { cur: 0, mmap, grow: Some(M::resize) }
     }
 }
 
 impl<'a, M: MMapMut> Seek for MMapWriter<'a, M> {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        match pos {
-            SeekFrom::Current(n) => self.seek_from_current(n),
-            SeekFrom::End(n) => self.seek_from_end(n),
-            SeekFrom::Start(n) => self.seek_from_start(n),
-        }
+        self.cur = seek_position(self.cur, self.mmap.len(), pos)?;
+        Ok(self.cur)
     }
 }
 
 impl<'a, M: MMapMut> Write for MMapWriter<'a, M> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Ok(0);
         }
-        if self.cur as usize >= self.mmap.len() {
-            // reaching end of file is no error, just return that 
-            // zero bytes were written.
-            return Ok(0);
+        let end = self.end_of_write(buf.len())?;
+        let cur = self.cur as usize;
+        self.reserve(end)?;
+        if cur >= self.mmap.len() {
+            let e = std::io::Error::new(std::io::ErrorKind::WriteZero, "Cannot write past the end of the mapping");
+            return Err(e);
         }
-        let top = std::cmp::min(self.cur as usize + buf.len(), self.mmap.len());
-        let n = top - self.cur as usize;
-        self.mmap[0..n].copy_from_slice(&buf);
+        let top = std::cmp::min(end, self.mmap.len());
+        let n = top - cur;
+        self.mmap[cur..top].copy_from_slice(&buf[..n]);
         self.cur = top as u64;
         Ok(n) 
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        // the buffers cannot all fit if their lengths overflow.
+        let total = bufs.iter().try_fold(0usize, |n, b| n.checked_add(b.len())).unwrap_or(usize::MAX);
+        if total == 0 {
+            return Ok(0);
+        }
+        // grow once for all buffers instead of once per buffer.
+        let end = self.end_of_write(total)?;
+        self.reserve(end)?;
+        let mut n = 0;
+        for buf in bufs.iter().filter(|b| !b.is_empty()) {
+            match self.write(buf) {
+                Ok(k) => {
+                    n += k;
+                    if k < buf.len() {
+                        break;
+                    }
+                }
+                Err(e) if n == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(n)
+    }
+
     fn flush(&mut self) -> std::io::Result<()> {
         self.mmap.sync(crate::MSyncType::Sync)
     }
 }
 
+/// Gives access to a mapping which is either owned directly or shared 
+/// through an `Arc`, see `MMapCursor`.
+pub trait MMapHandle {
+    type Target: MMap;
+    fn mmap(&self) -> &Self::Target;
+}
+
+impl<M: MMap> MMapHandle for M {
+    type Target = M;
+    fn mmap(&self) -> &M {
+        self
+    }
+}
+
+impl<M: MMap> MMapHandle for Arc<M> {
+    type Target = M;
+    fn mmap(&self) -> &M {
+        self
+    }
+}
+
+/// Cursor that owns its mapping, unlike `MMapReader` and `MMapWriter` it
+/// does not borrow and can be stored alongside other state or returned from
+/// functions. Reads work on an owned mapping as well as on an `Arc` of it,
+/// writes need an owned writable mapping and behave like `MMapWriter::new`.
+pub struct MMapCursor<H> {
+    cur: u64,
+    inner: H,
+}
+
+impl<H> MMapCursor<H> {
+    pub fn new(inner: H) -> Self {
+        Self { cur: 0, inner }
+    }
+
+    /// Return the current position of the cursor.
+    pub fn position(&self) -> u64 {
+        self.cur
+    }
+
+    pub fn set_position(&mut self, pos: u64) {
+        self.cur = pos;
+    }
+
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    /// Return the mapping, dropping the cursor.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+
+impl<H: MMapHandle> MMapCursor<H> {
+    fn reader(&self) -> MMapReader<'_, H::Target> {
+        MMapReader::new(self.inner.mmap()).at(self.cur)
+    }
+}
+
+impl<H: MMapHandle> Seek for MMapCursor<H> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.cur = seek_position(self.cur, self.inner.mmap().len(), pos)?;
+        Ok(self.cur)
+    }
+}
+
+impl<H: MMapHandle> Read for MMapCursor<H> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut reader = self.reader();
+        let n = reader.read(buf)?;
+        self.cur = reader.cur;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        let mut reader = self.reader();
+        reader.read_exact(buf)?;
+        self.cur = reader.cur;
+        Ok(())
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
+        let mut reader = self.reader();
+        let n = reader.read_vectored(bufs)?;
+        self.cur = reader.cur;
+        Ok(n)
+    }
+}
+
+impl<H: MMapHandle> BufRead for MMapCursor<H> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(self.reader().remaining())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cur += amt as u64;
+    }
+}
+
+impl<M: MMapMut> Write for MMapCursor<M> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut writer = MMapWriter::new(&mut self.inner);
+        writer.cur = self.cur;
+        let n = writer.write(buf)?;
+        self.cur = writer.cur;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let mut writer = MMapWriter::new(&mut self.inner);
+        writer.cur = self.cur;
+        let n = writer.write_vectored(bufs)?;
+        self.cur = writer.cur;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.sync(crate::MSyncType::Sync)
+    }
+}
+
+#[cfg(test)]
 mod writer_tests {
     use super::*;
     use crate::*;
@@ -151,11 +456,11 @@ mod writer_tests {
         let mut mmap = setup(24123);
         let patt = (0..mmap.len()).map(|x| (x % 255) as u8).collect::<Vec<u8>>();
         let mut writer = MMapWriter::new(&mut mmap);
-        writer.write(patt.as_slice()).unwrap();
+        writer.write_all(patt.as_slice()).unwrap();
 
         let mut rbuf = vec![0xff; mmap.len()];
         let mut reader = MMapReader::new(&mmap);
-        reader.read(rbuf.as_mut_slice()).unwrap();
+        reader.read_exact(rbuf.as_mut_slice()).unwrap();
 
         assert_eq!(rbuf, patt);
     }
@@ -165,16 +470,78 @@ mod writer_tests {
         let mut mmap = setup(24123);
         let patt = (0..mmap.len()).map(|x| (x % 255) as u8).collect::<Vec<u8>>();
         let mut writer = MMapWriter::new(&mut mmap);
-        writer.write(patt.as_slice()).unwrap();
+        writer.write_all(patt.as_slice()).unwrap();
 
         writer.flush().unwrap();
 
         let mut rbuf = vec![0xff; mmap.len()];
         let mut reader = MMapReader::new(&mmap);
-        reader.read(rbuf.as_mut_slice()).unwrap();
+        reader.read_exact(rbuf.as_mut_slice()).unwrap();
 
         assert_eq!(rbuf, patt);
     }
+
+    #[test]
+    fn seek_then_write() {
+        let mut mmap = setup(100);
+        let mut writer = MMapWriter::new(&mut mmap);
+        writer.seek(SeekFrom::Start(10)).unwrap();
+        writer.write_all(&[0xaa; 5]).unwrap();
+        writer.seek(SeekFrom::End(-3)).unwrap();
+        writer.write_all(&[0xbb; 3]).unwrap();
+        assert_eq!(mmap[9], 9);
+        assert_eq!(mmap[10..15], [0xaa; 5]);
+        assert_eq!(mmap[15], 15);
+        assert_eq!(mmap[97..], [0xbb; 3]);
+        assert_eq!(mmap[0], 0);
+    }
+
+    #[test]
+    fn overrun_is_shortened_then_fails() {
+        let mut mmap = setup(100);
+        let mut writer = MMapWriter::new(&mut mmap);
+        writer.seek(SeekFrom::Start(95)).unwrap();
+        assert_eq!(writer.write(&[0xcc; 10]).unwrap(), 5);
+        let e = writer.write(&[0xcc; 10]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+        writer.seek(SeekFrom::Start(1000)).unwrap();
+        assert!(writer.write(&[0xcc]).is_err());
+        writer.seek(SeekFrom::Start(u64::MAX)).unwrap();
+        let e = writer.write(&[0xcc]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        let e = writer.write_vectored(&[IoSlice::new(&[0xcc])]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(mmap.len(), 100);
+        assert_eq!(mmap[95..], [0xcc; 5]);
+    }
+
+    #[test]
+    fn write_vectored_at_cursor() {
+        let mut mmap = setup(10);
+        let mut writer = MMapWriter::new(&mut mmap);
+        writer.seek(SeekFrom::Start(2)).unwrap();
+        let n = writer.write_vectored(&[IoSlice::new(&[1; 3]), IoSlice::new(&[]), IoSlice::new(&[2; 10])]).unwrap();
+        assert_eq!(n, 8);
+        assert_eq!(mmap[..], [0, 1, 1, 1, 1, 2, 2, 2, 2, 2]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn extending_writer_grows_mapping() {
+        let mut mmap = setup(100);
+        let mut writer = MMapWriter::extending(&mut mmap);
+        writer.seek(SeekFrom::Start(90)).unwrap();
+        writer.write_all(&[0xdd; 20]).unwrap();
+        writer.seek(SeekFrom::Start(200)).unwrap();
+        let n = writer.write_vectored(&[IoSlice::new(&[0xee; 8]), IoSlice::new(&[0xef; 8])]).unwrap();
+        assert_eq!(n, 16);
+        assert_eq!(mmap.len(), 216);
+        assert_eq!(mmap[89], 89);
+        assert_eq!(mmap[90..110], [0xdd; 20]);
+        assert!(mmap[110..200].iter().all(|b| *b == 0));
+        assert_eq!(mmap[200..208], [0xee; 8]);
+        assert_eq!(mmap[208..], [0xef; 8]);
+    }
 }
 
 #[cfg(test)]
@@ -200,8 +567,8 @@ mod tests {
         let mut buf = vec![0xff; mmap.len()];
         let n = reader.read(&mut buf).unwrap();
         assert_eq!(n, mmap.len());
-        for i in 0..n {
-            assert_eq!(buf[i], (i % 255) as u8);
+        for (i, b) in buf.iter().enumerate().take(n) {
+            assert_eq!(*b, (i % 255) as u8);
         }
     }
 
@@ -234,8 +601,8 @@ mod tests {
         let mut buf = vec![0xff; 2 * mmap.len()];
         let n = reader.read(&mut buf).unwrap();
         assert_eq!(n, mmap.len()); // should only read n == mmap.len() either way!
-        for i in 0..n {
-            assert_eq!(buf[i], (i % 255) as u8);
+        for (i, b) in buf.iter().enumerate().take(n) {
+            assert_eq!(*b, (i % 255) as u8);
         }
     }
 
@@ -285,4 +652,198 @@ mod tests {
         assert_eq!(n, 0);
     }
 
+    #[test]
+    fn fill_buf_points_into_mapping() {
+        let mmap = setup(24999);
+        let mut reader = MMapReader::new(&mmap);
+        reader.seek(SeekFrom::Start(100)).unwrap();
+        let buf = reader.fill_buf().unwrap();
+        assert_eq!(buf.as_ptr(), mmap[100..].as_ptr());
+        assert_eq!(buf.len(), mmap.len() - 100);
+        reader.consume(50);
+        assert_eq!(reader.cur, 150);
+    }
+
+    #[test]
+    fn buf_read_lines() {
+        let mut mmap = setup(100);
+        mmap[..12].copy_from_slice(b"first\nsecond");
+        let reader = MMapReader::new(&mmap);
+        let first = reader.split(b'\n').next().unwrap().unwrap();
+        assert_eq!(first, b"first");
+    }
+
+    #[test]
+    fn read_exact_past_end() {
+        let mmap = setup(100);
+        let mut reader = MMapReader::new(&mmap);
+        let mut buf = vec![0xff; 60];
+        reader.read_exact(&mut buf).unwrap();
+        let e = reader.read_exact(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        // a failed read_exact does not move the cursor.
+        assert_eq!(reader.cur, 60);
+    }
+
+    #[test]
+    fn read_vectored_fills_buffers_in_order() {
+        let mmap = setup(10);
+        let mut reader = MMapReader::new(&mmap);
+        let mut a = [0xffu8; 4];
+        let mut b = [0xffu8; 8];
+        let n = reader.read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)]).unwrap();
+        assert_eq!(n, 10);
+        assert_eq!(a, [0, 1, 2, 3]);
+        assert_eq!(b, [4, 5, 6, 7, 8, 9, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn peek_and_take_slice() {
+        let mmap = setup(10);
+        let mut reader = MMapReader::new(&mmap);
+        assert_eq!(reader.peek(3), &[0, 1, 2]);
+        assert_eq!(reader.take_slice(3), &[0, 1, 2]);
+        assert_eq!(reader.take_slice(100), &mmap[3..]);
+        assert!(reader.peek(1).is_empty());
+
+        // the slices borrow the mapping and outlive the reader.
+        let head = MMapReader::new(&mmap).take_slice(4);
+        assert_eq!(head, &[0, 1, 2, 3]);
+    }
+}
+
+#[cfg(test)]
+mod streaming_tests {
+    use super::*;
+    use crate::*;
+
+    fn page_size() -> usize {
+        crate::base::get_page_size() as usize
+    }
+
+    #[test]
+    fn streaming_reads_everything() {
+        let ps = page_size();
+        let data = crate::testutil::pattern(0..64 * ps);
+        let tf = crate::testutil::TestFile::new(&data);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        let mut reader = MMapReader::streaming(&mmap, 8 * ps).unwrap().release_page_cache(&tf.fp, 0);
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; 1000];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, data);
+        // a shared file mapping still sees the file after pages were released.
+        assert_eq!(mmap[..], data[..]);
+    }
+
+    #[test]
+    fn streaming_keeps_pages_of_private_mappings() {
+        let ps = page_size();
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 32 * ps, MMapConfig::new().map_private()).unwrap();
+        mmap.fill(0xaa);
+        let mut reader = MMapReader::streaming(&mmap, ps).unwrap();
+        let mut slices = Vec::new();
+        for _ in 0..32 {
+            slices.push(reader.take_slice(ps));
+        }
+        assert!(slices.iter().all(|s| s.len() == ps && s.iter().all(|b| *b == 0xaa)));
+        let e = mmap.advise(MAdviseConfig::new().madv_dontneed()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        let e = mmap.advise_range(0..ps, MAdviseConfig::new().madv_dontneed()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn streaming_fill_buf_is_bounded_by_window() {
+        let ps = page_size();
+        let mmap = AnonMMapMut::new(AddrHint::None, 32 * ps, MMapConfig::new().map_private()).unwrap();
+        let mut reader = MMapReader::streaming(&mmap, 3 * ps + 1).unwrap();
+        assert_eq!(reader.fill_buf().unwrap().len(), 4 * ps);
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        assert_eq!(reader.fill_buf().unwrap().len(), 10);
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(reader.fill_buf().unwrap().len(), 4 * ps);
+    }
+}
+
+#[cfg(test)]
+mod cursor_tests {
+    use super::*;
+    use crate::*;
+
+    fn setup(len: usize) -> AnonMMap {
+        let mut mmap = AnonMMapMut::new(AddrHint::None, len, MMapConfig::new().map_private()).unwrap();
+        for (i, b) in mmap.iter_mut().enumerate() {
+            *b = (i % 255) as u8;
+        }
+        mmap.try_into().unwrap()
+    }
+
+    /// Connection state that keeps its reader next to other fields.
+    struct Connection {
+        id: u32,
+        reader: MMapCursor<AnonMMap>,
+    }
+
+    fn open_connection(len: usize) -> Connection {
+        Connection { id: 7, reader: MMapCursor::new(setup(len)) }
+    }
+
+    #[test]
+    fn cursor_stored_in_struct() {
+        let mut conn = open_connection(1000);
+        let mut buf = [0u8; 4];
+        conn.reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2, 3]);
+        assert_eq!(conn.reader.position(), 4);
+        assert_eq!(conn.id, 7);
+
+        let mmap = conn.reader.into_inner();
+        assert_eq!(mmap.len(), 1000);
+    }
+
+    #[test]
+    fn cursors_share_arc() {
+        let mmap = Arc::new(setup(1000));
+        let mut a = MMapCursor::new(mmap.clone());
+        let mut b = MMapCursor::new(mmap);
+        a.seek(SeekFrom::End(-10)).unwrap();
+        let mut rest = Vec::new();
+        a.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest.len(), 10);
+        assert_eq!(b.fill_buf().unwrap().len(), 1000);
+        b.consume(990);
+        assert_eq!(b.fill_buf().unwrap(), &rest[..]);
+    }
+
+    #[test]
+    fn cursor_read_lines_across_threads() {
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 13, MMapConfig::new().map_private()).unwrap();
+        mmap.copy_from_slice(b"one\ntwo\nthree");
+        let cursor = MMapCursor::new(mmap);
+        let lines = std::thread::spawn(move || {
+            cursor.lines().collect::<Result<Vec<_>, _>>().unwrap()
+        }).join().unwrap();
+        assert_eq!(lines, vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn cursor_write_then_read_back() {
+        let mmap = AnonMMapMut::new(AddrHint::None, 8, MMapConfig::new().map_private()).unwrap();
+        let mut cursor = MMapCursor::new(mmap);
+        cursor.seek(SeekFrom::Start(2)).unwrap();
+        cursor.write_all(&[9, 9, 9]).unwrap();
+        assert_eq!(cursor.position(), 5);
+        assert!(cursor.write_all(&[1; 4]).is_err());
+        cursor.set_position(0);
+        let mut buf = Vec::new();
+        cursor.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, vec![0, 0, 9, 9, 9, 1, 1, 1]);
+    }
 }
@@ -4,27 +4,195 @@ mod anon;
 mod io;
 mod filemap;
 mod mlock;
+mod shared;
+mod mapped;
+mod split;
+mod records;
+mod lineindex;
+mod positional;
+mod strategy;
+mod prefetch;
+mod windowed;
+mod concat;
+mod chain;
+mod fault;
+mod follow;
+mod atomic;
+mod error;
+#[cfg(test)]
+mod testutil;
 
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 
-#[allow(unused)]
-use config::{MAdviseConfig, MMapConfig};
-#[allow(unused)]
-use anon::{AnonMMap, AnonMMapMut, AnonExecutableMMap, AnonExecutableMMapMut};
-#[allow(unused)]
-use filemap::{FileMMap, FileMMapMut, ExecFileMMap, ExecFileMMapMut};
+pub use config::{MAdviseConfig, MMapConfig};
+pub use anon::{AnonMMap, AnonMMapMut, AnonExecutableMMap, AnonExecutableMMapMut};
+pub use filemap::{FileMMap, FileMMapMut, ExecFileMMap, ExecFileMMapMut, OpenMode};
+pub use io::{MMapReader, MMapWriter, MMapCursor, MMapHandle};
+pub use mlock::{MLock, IncoreInfo};
+pub use shared::SharedMMapMut;
+pub use mapped::MappedBytes;
+pub use split::{MMapSliceMut, ChunksMutPages};
+pub use records::{split_records, Lines, Utf8Lines, FindAll};
+pub use lineindex::LineIndex;
+pub use positional::{ByteSource, ByteSink};
+pub use strategy::{open_best, open_best_with, AccessStrategy, BestSource, OpenConfig};
+pub use prefetch::{Prefetcher, PrefetchHandle};
+pub use windowed::{WindowedFileMap, WindowGuard};
+pub use concat::{ConcatMap, Segment};
+pub use chain::ChainReader;
+pub use fault::install_sigbus_handler;
+pub use follow::FollowingFileMap;
+pub use atomic::AtomicMappedWrite;
+pub use error::MMapError;
+pub use base::{set_drop_error_hook, clear_drop_error_hook};
 
 /// Memory mapping with read only access.
+///
+/// # Thread safety
+///
+/// Every mapping type owns its region exclusively and unmaps it on drop, 
+/// so all of them are `Send`. The read only types are also `Sync`: their 
+/// pages can only be read through `&self`, which makes sharing them 
+/// between threads (e.g. in an `Arc`) as safe as sharing a `&[u8]`.
+///
+/// Note that this only covers accesses from within the process. Another 
+/// process writing to a shared file mapping is not synchronized with 
+/// the readers in this process.
 pub trait MMap: Deref<Target=[u8]> {
     fn as_ptr(&self) -> *const u8;
     fn sync(&self, typ: MSyncType) -> std::io::Result<()>;
     fn advise(&self, config: MAdviseConfig) -> std::io::Result<()>;
     fn unmap(self) -> std::io::Result<()>;
+
+    /// Like `sync` but only flushes the pages overlapping `range`.
+    fn sync_range(&self, range: Range<usize>, typ: MSyncType) -> std::io::Result<()> {
+        let (addr, len) = base::page_range(self.as_ptr(), self.len(), range)?;
+        base::msync_region(addr, len, &typ)
+    }
+
+    /// Like `advise` but only for the pages overlapping `range`.
+    fn advise_range(&self, range: Range<usize>, config: MAdviseConfig) -> std::io::Result<()> {
+        base::check_advice(&config, self.is_shared_file())?;
+        let (addr, len) = base::page_range(self.as_ptr(), self.len(), range)?;
+        base::madvise_region(addr, len, config.value())
+    }
+
+    /// Check if this is a MAP_SHARED file mapping. Only the pages of such
+    /// a mapping can be released with MADV_DONTNEED, they are read from
+    /// the file again afterwards. `advise` rejects the advice for all 
+    /// other mappings, whose pages would read as zeros.
+    fn is_shared_file(&self) -> bool {
+        false
+    }
+
+    /// Pass the bytes at `range` to `f`. If the mapped file was truncated
+    /// by another process and `f` touches a page beyond its new end, the
+    /// access returns UnexpectedEof instead of killing the process with 
+    /// SIGBUS. While `f` runs the faulting pages read as zeros.
+    ///
+    /// Afterwards the file mappings of this crate which own their file (see
+    /// `FileMMap::new_owned`) map the file over these pages again, i.e. they
+    /// fault again while the file is short and show its contents once it
+    /// grew back. For a private mapping, changes to pages between two
+    /// faulting pages are lost. In all other mappings the faulting pages
+    /// keep reading as zeros, and writes to them do not reach the file.
+    ///
+    /// Installs a process wide SIGBUS handler, see `install_sigbus_handler`.
+    fn try_read<R, F: FnOnce(&[u8]) -> R>(&self, range: Range<usize>, f: F) -> std::io::Result<R> where Self: Sized {
+        if range.start > range.end || range.end > self.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of the mapped region"));
+        }
+        let data = &self[range];
+        let map_again = |_, _| Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+        fault::guarded(data.as_ptr(), data.len(), libc::PROT_READ, map_again, || f(data))
+    }
+
+    /// Copy the bytes at `range`, like `try_read` a truncated file is 
+    /// reported as UnexpectedEof.
+    fn try_copy_from(&self, range: Range<usize>) -> std::io::Result<Vec<u8>> where Self: Sized {
+        self.try_read(range, |s| s.to_vec())
+    }
+
+    /// Fault in the pages overlapping `range` so that later reads do not
+    /// block on page faults. Fails with UnexpectedEof if the mapped file is
+    /// shorter than `range`.
+    ///
+    /// Kernels older than 5.14 cannot populate a region, the pages are
+    /// touched one by one instead. File mappings that own their file only
+    /// touch the pages up to the end of the file, other file mappings fail
+    /// with Unsupported.
+    fn populate_read(&self, range: Range<usize>) -> std::io::Result<()> {
+        let (addr, len) = base::page_range(self.as_ptr(), self.len(), range)?;
+        base::populate_region(addr, len, false, || Ok(base::Extent::All))
+    }
+
+    /// Split the mapping into read only chunks of `pages` pages each (the 
+    /// last chunk may be shorter) which are processed in parallel.
+    #[cfg(feature = "rayon")]
+    fn par_chunks(&self, pages: usize) -> rayon::slice::Chunks<'_, u8> {
+        use rayon::slice::ParallelSlice;
+        self.deref().par_chunks(pages * base::get_page_size() as usize)
+    }
 }
 
 /// Memory mapping with read and write permission.
+///
+/// # Thread safety
+///
+/// Writable mappings are `Send` but not `Sync`, writes require `&mut self`
+/// just like for a `Vec<u8>`. To have several threads write to the same 
+/// region concurrently, wrap the mapping in a [`SharedMMapMut`] which only
+/// hands out atomic views of the mapped memory.
 pub trait MMapMut: MMap + DerefMut<Target=[u8]> {
     fn as_mut_ptr(&mut self) -> *mut u8;
+
+    /// Divide the mapping into two disjoint writable views at `mid`, which 
+    /// must be a multiple of the page size (or the length of the mapping). 
+    /// Each view can be synced and advised on its own and sent to another
+    /// thread.
+    fn split_at_page_mut(&mut self, mid: usize) -> std::io::Result<(MMapSliceMut<'_>, MMapSliceMut<'_>)> {
+        let len = self.len();
+        let ps = base::get_page_size() as usize;
+        if mid > len || (mid != len && !mid.is_multiple_of(ps)) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "split point is not page aligned"));
+        }
+        let shared_file = self.is_shared_file();
+        let ptr = self.as_mut_ptr();
+        unsafe {
+            Ok((MMapSliceMut::new(ptr, mid, shared_file), MMapSliceMut::new(ptr.add(mid), len - mid, shared_file)))
+        }
+    }
+
+    /// Like `populate_read` but faults the pages in writable, which breaks
+    /// copy-on-write sharing of private mappings ahead of time.
+    fn populate_write(&mut self, range: Range<usize>) -> std::io::Result<()> {
+        let (addr, len) = base::page_range(self.as_mut_ptr(), self.len(), range)?;
+        base::populate_region(addr, len, true, || Ok(base::Extent::All))
+    }
+
+    /// Iterate over disjoint writable views of `pages` pages each, the last
+    /// view may be shorter. Panics if `pages` is zero.
+    fn chunks_mut_pages(&mut self, pages: usize) -> ChunksMutPages<'_> {
+        assert!(pages != 0, "chunk size must be non-zero");
+        // a chunk larger than the address space covers all of the mapping.
+        let chunk = pages.saturating_mul(base::get_page_size() as usize);
+        let (len, shared_file) = (self.len(), self.is_shared_file());
+        ChunksMutPages::new(self.as_mut_ptr(), len, chunk, shared_file)
+    }
+
+    /// Like `chunks_mut_pages` but the views are processed in parallel.
+    #[cfg(feature = "rayon")]
+    fn par_chunks_mut(&mut self, pages: usize) -> rayon::vec::IntoIter<MMapSliceMut<'_>> {
+        use rayon::iter::IntoParallelIterator;
+        self.chunks_mut_pages(pages).collect::<Vec<_>>().into_par_iter()
+    }
+}
+
+/// Writable memory mapping whose length can be changed after it was created.
+pub trait MMapResize: MMapMut {
+    /// Grow or shrink the mapping to `new_len` bytes. The mapping may move
+    /// to a new address, pointers obtained before are invalidated.
+    fn resize(&mut self, new_len: usize) -> std::io::Result<()>;
 }
 
 /// Executable read only memory mapping.
@@ -43,6 +211,7 @@ pub trait MMapExecMut: MMapExec + MMapMut {}
 /// own.
 /// If MAP_FIXED is requested the system must map the region at the requested 
 /// address, possibly stealing pages from existing mappings.
+#[derive(Clone, Copy, Debug)]
 pub enum AddrHint {
     None,
     Addr(*mut u8),
@@ -70,3 +239,21 @@ pub enum MSyncType {
     /// Msync invalidetes only cached data, not sync flag is set.
     Invalidate,
 }
+
+/// What dropping a file mapping does before it is unmapped, see 
+/// `FileMMapMut::set_drop_policy`. `unmap` applies the policy as well but
+/// returns the errors, which drop passes to the hook set with 
+/// `set_drop_error_hook`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Only unmap, the kernel writes dirty pages back at some later time.
+    #[default]
+    None,
+    /// Start writing dirty pages back with MS_ASYNC.
+    Async,
+    /// Write dirty pages back with MS_SYNC.
+    Sync,
+    /// Write dirty pages back with MS_SYNC and flush the file data with
+    /// fdatasync. Requires a mapping that owns its file.
+    SyncData,
+}
@@ -0,0 +1,312 @@
+use crate::{AddrHint, AtomicMappedWrite, FileMMap, MMapConfig};
+use std::fs::{File, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"MMLIDX01";
+const HEADER_LEN: usize = 40;
+
+/// Size and modification time of the indexed file, used to detect that
+/// an index no longer matches the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileStamp {
+    fn of(meta: &Metadata) -> Self {
+        Self { len: meta.len(), mtime: meta.mtime(), mtime_nsec: meta.mtime_nsec() }
+    }
+}
+
+/// Index of the line start offsets of a mapped text file which allows to
+/// jump to any line without scanning the file.
+///
+/// Lines are split on `\n` just like `FileMMap::lines`. The index can be
+/// persisted in a sidecar file, which is reused as long as size and
+/// modification time of the indexed file do not change.
+pub struct LineIndex {
+    path: PathBuf,
+    sidecar: Option<PathBuf>,
+    mmap: FileMMap,
+    starts: Vec<u64>,
+    stamp: FileStamp,
+}
+
+impl LineIndex {
+    /// Scan the file at `path` and build the index in memory.
+    pub fn build<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &file, 0)?;
+        let starts = scan(&mmap);
+        Ok(Self { path, sidecar: None, mmap, starts, stamp })
+    }
+
+    /// Open the index of the file at `path`, stored next to it with an
+    /// additional `.lidx` extension.
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut sidecar = path.as_ref().as_os_str().to_owned();
+        sidecar.push(".lidx");
+        Self::open_with_sidecar(path, sidecar)
+    }
+
+    /// Load the index of the file at `path` from `sidecar`. If the sidecar
+    /// does not exist or is stale the file is scanned again and the new
+    /// index is written to the sidecar.
+    pub fn open_with_sidecar<P: AsRef<Path>, S: AsRef<Path>>(path: P, sidecar: S) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let sidecar = sidecar.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &file, 0)?;
+        let starts = match load(&sidecar, stamp, &mmap) {
+            Some(starts) => starts,
+            None => {
+                let starts = scan(&mmap);
+                persist(&sidecar, stamp, &starts)?;
+                starts
+            }
+        };
+        Ok(Self { path, sidecar: Some(sidecar), mmap, starts, stamp })
+    }
+
+    /// Write the index to `sidecar`.
+    pub fn persist<S: AsRef<Path>>(&self, sidecar: S) -> std::io::Result<()> {
+        persist(sidecar.as_ref(), self.stamp, &self.starts)
+    }
+
+    /// Check if size or modification time of the indexed file have changed
+    /// since the index was built.
+    pub fn is_stale(&self) -> std::io::Result<bool> {
+        let stamp = FileStamp::of(&std::fs::metadata(&self.path)?);
+        Ok(stamp != self.stamp)
+    }
+
+    /// Rebuild the index (and its sidecar) if the indexed file has changed.
+    /// Returns true if the index was rebuilt.
+    pub fn refresh(&mut self) -> std::io::Result<bool> {
+        if !self.is_stale()? {
+            return Ok(false);
+        }
+        let fresh = match &self.sidecar {
+            Some(sidecar) => Self::open_with_sidecar(&self.path, sidecar)?,
+            None => Self::build(&self.path)?,
+        };
+        *self = fresh;
+        Ok(true)
+    }
+
+    /// Return the number of lines in the file.
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Return line `n` (counting from zero) without its line ending.
+    pub fn line(&self, n: usize) -> Option<&[u8]> {
+        let begin = *self.starts.get(n)? as usize;
+        let end = match self.starts.get(n + 1) {
+            Some(s) => *s as usize - 1,
+            None => self.mmap.len(),
+        };
+        let line = &self.mmap[begin..end];
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+
+    /// Return the number of the line which contains the byte at `off`.
+    pub fn line_of_offset(&self, off: usize) -> Option<usize> {
+        if off >= self.mmap.len() {
+            return None;
+        }
+        match self.starts.binary_search(&(off as u64)) {
+            Ok(n) => Some(n),
+            Err(n) => Some(n - 1),
+        }
+    }
+}
+
+/// Return the start offsets of all lines in `data`.
+fn scan(data: &[u8]) -> Vec<u64> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let mut starts = vec![0u64];
+    starts.extend(newline_ends(data).filter(|n| (*n as usize) < data.len()));
+    starts
+}
+
+#[cfg(not(feature = "rayon"))]
+fn newline_ends(data: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    memchr::memchr_iter(b'\n', data).map(|i| i as u64 + 1)
+}
+
+#[cfg(feature = "rayon")]
+fn newline_ends(data: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    use rayon::prelude::*;
+    const CHUNK: usize = 1 << 22;
+    let parts = data.par_chunks(CHUNK).enumerate().map(|(i, chunk)| {
+        memchr::memchr_iter(b'\n', chunk).map(|n| (i * CHUNK + n) as u64 + 1).collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+    parts.into_iter().flatten()
+}
+
+/// Load the line starts of `data` from `sidecar`. The sidecar is ignored
+/// unless it matches `stamp` and holds valid line starts of `data`.
+fn load(sidecar: &Path, stamp: FileStamp, data: &[u8]) -> Option<Vec<u64>> {
+    let file = File::open(sidecar).ok()?;
+    let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &file, 0).ok()?;
+    if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC {
+        return None;
+    }
+    let field = |i: usize| u64::from_le_bytes(mmap[8 + 8 * i..16 + 8 * i].try_into().unwrap());
+    let stored = FileStamp { len: field(0), mtime: field(1) as i64, mtime_nsec: field(2) as i64 };
+    let size = usize::try_from(field(3)).ok()?.checked_mul(8)?.checked_add(HEADER_LEN)?;
+    if stored != stamp || mmap.len() != size {
+        return None;
+    }
+    let starts: Vec<u64> = mmap[HEADER_LEN..].chunks_exact(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+    // the stamp may match a different file, the starts must be usable.
+    if starts.first().is_some_and(|s| *s != 0) || starts.is_empty() != data.is_empty() {
+        return None;
+    }
+    let valid = starts.windows(2).all(|w| w[0] < w[1])
+        && starts.iter().skip(1).all(|s| (*s as usize) < data.len() && data[*s as usize - 1] == b'\n');
+    valid.then_some(starts)
+}
+
+/// Write the index to `sidecar`, which is replaced atomically so that
+/// readers never see a partially written index.
+fn persist(sidecar: &Path, stamp: FileStamp, starts: &[u64]) -> std::io::Result<()> {
+    let mut w = AtomicMappedWrite::new(sidecar, (HEADER_LEN + 8 * starts.len()) as u64)?;
+    w[0..8].copy_from_slice(MAGIC);
+    let header = [stamp.len, stamp.mtime as u64, stamp.mtime_nsec as u64, starts.len() as u64];
+    for (i, v) in header.iter().enumerate() {
+        w[8 + 8 * i..16 + 8 * i].copy_from_slice(&v.to_le_bytes());
+    }
+    for (dst, s) in w[HEADER_LEN..].chunks_exact_mut(8).zip(starts) {
+        dst.copy_from_slice(&s.to_le_bytes());
+    }
+    w.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestFile;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn sidecar(path: &Path) -> PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".lidx");
+        PathBuf::from(sidecar)
+    }
+
+    /// Create a file holding `text` whose sidecar is removed with it.
+    fn test_file(text: &str) -> TestFile {
+        let mut tf = TestFile::new(text.as_bytes());
+        tf.remove_also(sidecar(&tf.path));
+        tf
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        std::fs::read(path).unwrap()
+    }
+
+    fn text(n: usize) -> String {
+        (0..n).map(|i| format!("{}:{}\n", i, "x".repeat(i % 17))).collect()
+    }
+
+    #[test]
+    fn lines_by_number() {
+        let text = text(500);
+        let tf = test_file(&text);
+        let idx = LineIndex::build(&tf.path).unwrap();
+        let expected = text.lines().collect::<Vec<_>>();
+        assert_eq!(idx.line_count(), expected.len());
+        for (n, l) in expected.iter().enumerate() {
+            assert_eq!(idx.line(n).unwrap(), l.as_bytes());
+        }
+        assert!(idx.line(500).is_none());
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        let tf = test_file("a\r\nbb\nccc");
+        let idx = LineIndex::build(&tf.path).unwrap();
+        assert_eq!(idx.line_count(), 3);
+        assert_eq!(idx.line(0).unwrap(), b"a");
+        assert_eq!(idx.line(2).unwrap(), b"ccc");
+    }
+
+    #[test]
+    fn offsets_to_lines() {
+        let tf = test_file("ab\ncd\n\nef\n");
+        let idx = LineIndex::build(&tf.path).unwrap();
+        let lines = [0, 0, 0, 1, 1, 1, 2, 3, 3, 3];
+        for (off, l) in lines.iter().enumerate() {
+            assert_eq!(idx.line_of_offset(off), Some(*l));
+        }
+        assert_eq!(idx.line_of_offset(10), None);
+    }
+
+    #[test]
+    fn sidecar_is_reused_and_rebuilt() {
+        let text = text(200);
+        let tf = test_file(&text);
+        let idx = LineIndex::open(&tf.path).unwrap();
+        assert_eq!(idx.line_count(), 200);
+        let stamp = FileStamp::of(&std::fs::metadata(&tf.path).unwrap());
+        assert_eq!(load(&sidecar(&tf.path), stamp, &read(&tf.path)).unwrap(), idx.starts);
+
+        // reopening uses the sidecar.
+        let idx = LineIndex::open(&tf.path).unwrap();
+        assert_eq!(idx.line(199).unwrap(), text.lines().last().unwrap().as_bytes());
+        assert!(!idx.is_stale().unwrap());
+
+        // appending to the file invalidates the sidecar.
+        let mut fp = OpenOptions::new().append(true).open(&tf.path).unwrap();
+        fp.write_all(b"one more\n").unwrap();
+        assert!(idx.is_stale().unwrap());
+        let stamp = FileStamp::of(&std::fs::metadata(&tf.path).unwrap());
+        assert!(load(&sidecar(&tf.path), stamp, &read(&tf.path)).is_none());
+
+        let mut idx = idx;
+        assert!(idx.refresh().unwrap());
+        assert_eq!(idx.line_count(), 201);
+        assert_eq!(idx.line(200).unwrap(), b"one more");
+        assert!(load(&sidecar(&tf.path), stamp, &read(&tf.path)).is_some());
+    }
+
+    #[test]
+    fn corrupt_sidecar_is_rebuilt() {
+        let text = text(50);
+        let tf = test_file(&text);
+        let idx = LineIndex::open(&tf.path).unwrap();
+        let stamp = FileStamp::of(&std::fs::metadata(&tf.path).unwrap());
+        let valid = read(&sidecar(&tf.path));
+
+        // a count which overflows the expected sidecar size.
+        let mut bad = valid.clone();
+        bad[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(sidecar(&tf.path), &bad).unwrap();
+        assert!(load(&sidecar(&tf.path), stamp, text.as_bytes()).is_none());
+
+        // offsets which are not line starts, beyond the end or not increasing.
+        for (i, v) in [(1, 5u64), (1, 1 << 40), (2, 1), (0, 3)] {
+            let mut bad = valid.clone();
+            bad[HEADER_LEN + 8 * i..HEADER_LEN + 8 * i + 8].copy_from_slice(&v.to_le_bytes());
+            std::fs::write(sidecar(&tf.path), &bad).unwrap();
+            assert!(load(&sidecar(&tf.path), stamp, text.as_bytes()).is_none());
+        }
+
+        // opening rebuilds the index and replaces the sidecar.
+        let reopened = LineIndex::open(&tf.path).unwrap();
+        assert_eq!(reopened.starts, idx.starts);
+        assert_eq!(read(&sidecar(&tf.path)), valid);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::MMap;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::Arc;
+
+/// Cheaply cloneable, reference counted view into a memory mapping.
+///
+/// Cloning and slicing only touch the reference count, the bytes are never
+/// copied. The mapping is unmapped when the last view referencing it is
+/// dropped, so views can be handed to other threads or stored with any
+/// lifetime.
+#[derive(Clone)]
+pub struct MappedBytes {
+    owner: Arc<dyn Deref<Target = [u8]> + Send + Sync>,
+    off: usize,
+    len: usize,
+}
+
+impl MappedBytes {
+    /// Take ownership of the mapping, the returned view covers all of it.
+    pub fn new<M: MMap + Send + Sync + 'static>(mmap: M) -> Self {
+        Self::from_arc(Arc::new(mmap))
+    }
+
+    /// Create a view of a mapping that is already shared.
+    pub fn from_arc<M: MMap + Send + Sync + 'static>(mmap: Arc<M>) -> Self {
+        let len = mmap.len();
+        Self { owner: mmap, off: 0, len }
+    }
+
+    /// Return length of the view.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return a new view of the given range relative to this view. Panics
+    /// if the range is out of bounds, like slice indexing does.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let begin = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).expect("attempted to index slice from after maximum usize"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n.checked_add(1).expect("attempted to index slice up to maximum usize"),
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => self.len,
+        };
+        assert!(begin <= end, "range start {} is greater than end {}", begin, end);
+        assert!(end <= self.len, "range end {} out of bounds for view of length {}", end, self.len);
+        Self { owner: self.owner.clone(), off: self.off + begin, len: end - begin }
+    }
+
+    /// Return a new view of a subslice of this view. Panics if `subset`
+    /// is not contained in this view.
+    pub fn slice_ref(&self, subset: &[u8]) -> Self {
+        if subset.is_empty() {
+            return self.slice(0..0);
+        }
+        let base = self.as_ptr() as usize;
+        let sub = subset.as_ptr() as usize;
+        assert!(sub >= base && sub + subset.len() <= base + self.len, "subset is not part of this view");
+        let off = sub - base;
+        self.slice(off..off + subset.len())
+    }
+
+    /// Check if both views belong to the same mapping.
+    pub(crate) fn same_mapping(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Arc::as_ptr(&self.owner), Arc::as_ptr(&other.owner))
+    }
+
+    /// Return the range of the mapping this view covers.
+    pub(crate) fn mapping_range(&self) -> std::ops::Range<usize> {
+        self.off..self.off + self.len
+    }
+
+    /// Return a view of `range` of the whole mapping, regardless of the 
+    /// part this view covers.
+    pub(crate) fn with_mapping_range(&self, range: std::ops::Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.owner.len());
+        Self { owner: self.owner.clone(), off: range.start, len: range.end - range.start }
+    }
+}
+
+impl Deref for MappedBytes {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        &self.owner[self.off..self.off + self.len]
+    }
+}
+
+impl AsRef<[u8]> for MappedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+impl std::fmt::Debug for MappedBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedBytes")
+            .field("off", &self.off)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<MappedBytes> for bytes::Bytes {
+    fn from(view: MappedBytes) -> Self {
+        bytes::Bytes::from_owner(view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnonMMapMut, AnonMMap, AddrHint, MMapConfig};
+
+    fn setup(len: usize) -> MappedBytes {
+        let mut mmap = AnonMMapMut::new(AddrHint::None, len, MMapConfig::new().map_private()).unwrap();
+        for (i, b) in mmap.iter_mut().enumerate() {
+            *b = (i % 255) as u8;
+        }
+        let mmap: AnonMMap = mmap.try_into().unwrap();
+        MappedBytes::new(mmap)
+    }
+
+    #[test]
+    fn slice_of_slice() {
+        let view = setup(10000);
+        let a = view.slice(100..5000);
+        let b = a.slice(10..=19);
+        assert_eq!(a.len(), 4900);
+        assert_eq!(b.len(), 10);
+        assert_eq!(b[..], view[110..120]);
+        assert_eq!(a.slice(..)[..], view[100..5000]);
+        assert!(view.slice(10..10).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn slice_out_of_bounds() {
+        let view = setup(100);
+        let _ = view.slice(50..101);
+    }
+
+    #[test]
+    #[should_panic(expected = "attempted to index slice up to maximum usize")]
+    fn slice_up_to_max_usize() {
+        let view = setup(100);
+        let _ = view.slice(..=usize::MAX);
+    }
+
+    #[test]
+    fn slice_ref_finds_offset() {
+        let view = setup(10000);
+        let sub = view.slice_ref(&view[300..400]);
+        assert_eq!(sub[..], view[300..400]);
+        assert!(view.slice_ref(&[]).is_empty());
+    }
+
+    #[test]
+    fn views_outlive_original_and_cross_threads() {
+        let view = setup(10000);
+        let expected = view[..].to_vec();
+        let parts = (0..4).map(|i| view.slice(i * 2500..(i + 1) * 2500)).collect::<Vec<_>>();
+        drop(view);
+        let handles = parts.into_iter().map(|p| std::thread::spawn(move || p.to_vec())).collect::<Vec<_>>();
+        let joined = handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(joined, expected);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn into_bytes() {
+        let view = setup(10000);
+        let b: bytes::Bytes = view.slice(10..20).into();
+        assert_eq!(b[..], view[10..20]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::MMap;
+use crate::error::{last_errno, MMapError};
 use libc::{mlock, munlock, mincore};
 
 /// Locks a memory region in physical memory.
@@ -8,10 +9,15 @@ pub struct MLock<'a, M: MMap> {
 
 impl<'a, M: MMap> MLock<'a, M> {
     pub fn new(handle: &'a M) -> std::io::Result<Self> {
+        // the dangling pointer of an empty mapping is not page aligned, the
+        // kernel would round the region up to a page at address zero.
+        if handle.is_empty() {
+            return Ok(Self { handle });
+        }
         unsafe {
             let rc = mlock(handle.as_ptr() as *const libc::c_void, handle.len());
             if rc != 0 {
-                return Err(std::io::Error::last_os_error());
+                return Err(MMapError::Mlock { errno: last_errno(), addr: handle.as_ptr() as usize, len: handle.len() }.into());
             }
         }
         Ok(Self { handle })
@@ -21,11 +27,14 @@ impl<'a, M: MMap> MLock<'a, M> {
     /// caller the opportunity to recognize and handle erros (as opposed to Drop which
     /// this type also implements).
     pub fn unlock(self) -> std::io::Result<()> {
+        if self.handle.is_empty() {
+            return Ok(());
+        }
         unsafe {
             let rc = munlock(self.handle.as_ptr() as *const libc::c_void, self.handle.len());
             if rc != 0 {
-                let err = std::io::Error::last_os_error();
-                return Err(err);
+                let (addr, len) = (self.handle.as_ptr() as usize, self.handle.len());
+                return Err(MMapError::Munlock { errno: last_errno(), addr, len }.into());
             }
         }
         Ok(())
@@ -34,57 +43,75 @@ impl<'a, M: MMap> MLock<'a, M> {
 
 impl<'a, M: MMap> Drop for MLock<'a, M> {
     fn drop(&mut self) {
+        if self.handle.is_empty() {
+            return;
+        }
         unsafe {
             let _ = munlock(self.handle.as_ptr() as *const libc::c_void, self.handle.len());
         }
     }
 }
 
-struct IncoreInfo {
-    pinfo: *const libc::c_char,
-    plen: usize,
+/// Residency of the pages of a mapping as reported by the mincore syscall.
+pub struct IncoreInfo {
+    pinfo: Vec<u8>,
 }
 
 impl IncoreInfo {
     pub fn read<M: MMap>(mmap: &M) -> std::io::Result<Self> {
+        Self::read_region(mmap.as_ptr(), mmap.len())
+    }
+
+    /// Read the residency of the pages overlapping `range` of the mapping.
+    /// Page 0 of the result is the page containing `range.start`.
+    pub fn read_range<M: MMap>(mmap: &M, range: std::ops::Range<usize>) -> std::io::Result<Self> {
+        let (addr, len) = crate::base::page_range(mmap.as_ptr(), mmap.len(), range)?;
+        Self::read_region(addr, len)
+    }
+
+    pub(crate) fn read_region(addr: *const u8, len: usize) -> std::io::Result<Self> {
+        // an empty mapping has no pages, its pointer is dangling.
+        if len == 0 {
+            return Ok(Self { pinfo: Vec::new() });
+        }
+        if !crate::base::ptr_is_page_aligned(addr) {
+            let err = std::io::Error::new(std::io::ErrorKind::InvalidInput, "mmap address is not page aligned");
+            return Err(err);
+        }
+        let page_size = crate::base::get_page_size();
+        if page_size <= 0 {
+            let err = std::io::Error::other("page size is invalid");
+            return Err(err);
+        }
+        // mincore writes one status byte per page of the region.
+        let plen = len.div_ceil(page_size as usize);
+        let mut pinfo = vec![0u8; plen];
         unsafe {
-            let pinfo: *mut libc::c_char = std::ptr::null_mut();
-            if !crate::base::ptr_is_page_aligned(mmap.as_ptr()) {
-                let err = std::io::Error::new(std::io::ErrorKind::InvalidInput, "mmap address is not page aligned");
-                return Err(err);
-            }
-            let rc = mincore(mmap.as_ptr() as *const libc::c_void, mmap.len(), pinfo);
+            let rc = mincore(addr as *mut libc::c_void, len, pinfo.as_mut_ptr() as *mut _);
             if rc != 0 {
-                let err = std::io::Error::last_os_error();
-                return Err(err);
-            }
-            let page_size = crate::base::get_page_size();
-            if page_size <= 0 {
-                let err = std::io::Error::new(std::io::ErrorKind::Other, "page size is invalid");
-                return Err(err);
+                return Err(MMapError::Mincore { errno: last_errno(), addr: addr as usize, len }.into());
             }
-            let plen = (mmap.len() + page_size as usize + 1) / page_size as usize;
-            Ok(Self { pinfo, plen })
         }
+        Ok(Self { pinfo })
     }
 
-    #[inline]
-    unsafe fn view_vec(&self) -> &[i8] {
-        std::slice::from_raw_parts(self.pinfo, self.plen)
+    pub fn flagvec_len(&self) -> usize {
+        self.pinfo.len()
     }
 
-    pub fn flagvec_len(&self) -> usize {
-        self.plen
+    pub fn page_flagbyte(&self, pageidx: usize) -> Option<u8> {
+        self.pinfo.get(pageidx).copied()
     }
 
-    pub fn page_flagbyte(&self, pageidx: usize) -> Option<i8> {
-        if self.plen >= pageidx {
-            return None;
-        }
-        unsafe {
-            let view = self.view_vec();
-            Some(view[pageidx])
-        }
+    /// Check if the page is resident in memory, i.e. accessing it does not
+    /// cause a major page fault.
+    pub fn is_resident(&self, pageidx: usize) -> bool {
+        self.page_flagbyte(pageidx).is_some_and(|f| f & 1 != 0)
+    }
+
+    /// Return the number of resident pages.
+    pub fn resident_count(&self) -> usize {
+        self.pinfo.iter().filter(|f| *f & 1 != 0).count()
     }
 }
 
@@ -92,15 +119,40 @@ impl IncoreInfo {
 #[cfg(test)] 
 mod tests {
     use super::*;
-    use std::fs::{OpenOptions, File};
     use crate::{config::MMapConfig, AddrHint};
 
     #[test]
     fn atest() {
         let ahint = AddrHint::None;
         let conf = MMapConfig::new().map_private();
-        let mut mmap = crate::AnonMMapMut::new(ahint, 999999, conf).unwrap();
+        let mmap = crate::AnonMMapMut::new(ahint, 999999, conf).unwrap();
+        let icinfo = IncoreInfo::read(&mmap).unwrap();
+        let ps = crate::base::get_page_size() as usize;
+        assert_eq!(icinfo.flagvec_len(), 999999usize.div_ceil(ps));
+    }
+
+    #[test]
+    fn touched_pages_are_resident() {
+        let ps = crate::base::get_page_size() as usize;
+        let conf = MMapConfig::new().map_private();
+        let mut mmap = crate::AnonMMapMut::new(AddrHint::None, 8 * ps, conf).unwrap();
+        mmap[2 * ps] = 1;
+        mmap[5 * ps + 10] = 1;
         let icinfo = IncoreInfo::read(&mmap).unwrap();
-        assert_eq!(icinfo.flagvec_len(), 0);
+        assert!(icinfo.is_resident(2));
+        assert!(icinfo.is_resident(5));
+        assert!(!icinfo.is_resident(8));
+        assert_eq!(icinfo.resident_count(), 2);
+
+        let icinfo = IncoreInfo::read_range(&mmap, 2 * ps + 1..3 * ps).unwrap();
+        assert_eq!(icinfo.flagvec_len(), 1);
+        assert!(icinfo.is_resident(0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn empty_mapping() {
+        let mmap = crate::AnonMMapMut::new(AddrHint::None, 0, MMapConfig::new().map_private()).unwrap();
+        assert_eq!(IncoreInfo::read(&mmap).unwrap().flagvec_len(), 0);
+        MLock::new(&mmap).unwrap().unlock().unwrap();
+    }
+}
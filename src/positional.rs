@@ -0,0 +1,231 @@
+use crate::{MMap, MMapMut, MappedBytes, SharedMMapMut};
+use std::sync::atomic::Ordering;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+/// Source of bytes that can be read at arbitrary offsets, mirroring
+/// `std::os::unix::fs::FileExt`. It is implemented by all mappings and by
+/// `File` (using pread), so code can switch between mapped and plain file
+/// access, also at runtime through `&dyn ByteSource`.
+pub trait ByteSource {
+    /// Return the number of bytes available.
+    fn size(&self) -> std::io::Result<u64>;
+
+    /// Read bytes starting at `offset` into `buf`, returns the number of
+    /// bytes read. Reading at or past the end returns 0.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+
+    /// Read exactly `buf.len()` bytes starting at `offset`.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !buf.is_empty() {
+            let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Sink of bytes that can be written at arbitrary offsets, mirroring
+/// `std::os::unix::fs::FileExt`, i.e. writes only need `&self`. It is
+/// implemented by `File` and by `SharedMMapMut`, wrap a writable mapping in
+/// the latter to use it as a sink. Note that a mapping, unlike a file, is 
+/// not extended by writes past its end.
+pub trait ByteSink: ByteSource {
+    /// Write bytes from `buf` starting at `offset`, returns the number of
+    /// bytes written.
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize>;
+
+    /// Write all of `buf` starting at `offset`.
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(buf, offset) {
+                Ok(0) => {
+                    let e = std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer");
+                    return Err(e);
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy from `data` at `offset` into `buf`.
+fn read_slice_at(data: &[u8], buf: &mut [u8], offset: u64) -> usize {
+    if offset >= data.len() as u64 {
+        return 0;
+    }
+    let rem = &data[offset as usize..];
+    let n = std::cmp::min(rem.len(), buf.len());
+    buf[..n].copy_from_slice(&rem[..n]);
+    n
+}
+
+impl<M: MMap> ByteSource for M {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        Ok(read_slice_at(self, buf, offset))
+    }
+}
+
+impl<M: MMapMut> ByteSource for SharedMMapMut<M> {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let data = self.as_atomic_slice();
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+        let rem = &data[offset as usize..];
+        let n = std::cmp::min(rem.len(), buf.len());
+        for (b, a) in buf[..n].iter_mut().zip(rem) {
+            *b = a.load(Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+impl<M: MMapMut> ByteSink for SharedMMapMut<M> {
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        let data = self.as_atomic_slice();
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+        let rem = &data[offset as usize..];
+        let n = std::cmp::min(rem.len(), buf.len());
+        for (a, b) in rem[..n].iter().zip(buf) {
+            a.store(*b, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+impl ByteSource for MappedBytes {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        Ok(read_slice_at(self, buf, offset))
+    }
+}
+
+impl ByteSource for File {
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+}
+
+impl ByteSink for File {
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        FileExt::write_at(self, buf, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddrHint, MMapConfig, FileMMap, FileMMapMut, AnonMMapMut, MSyncType};
+    use crate::testutil::TestFile;
+
+    fn checksum(src: &dyn ByteSource, offset: u64, len: usize) -> std::io::Result<u64> {
+        let mut buf = vec![0u8; len];
+        src.read_exact_at(&mut buf, offset)?;
+        Ok(buf.iter().map(|b| *b as u64).sum())
+    }
+
+    #[test]
+    fn file_and_mapping_are_interchangeable() {
+        let tf = TestFile::pattern(10000);
+        let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        let sources: Vec<&dyn ByteSource> = vec![&tf.fp, &mmap];
+        for src in sources {
+            assert_eq!(src.size().unwrap(), 10000);
+            assert_eq!(checksum(src, 100, 500).unwrap(), checksum(&tf.fp, 100, 500).unwrap());
+            let e = checksum(src, 9990, 20).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+            let mut buf = [0u8; 4];
+            assert_eq!(src.read_at(&mut buf, 20000).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn write_at_mapping_visible_in_file() {
+        let tf = TestFile::pattern(10000);
+        let mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        let mmap = SharedMMapMut::new(mmap);
+        mmap.write_all_at(&[0xee; 16], 5000).unwrap();
+        mmap.sync(MSyncType::Sync).unwrap();
+        let mut buf = [0u8; 16];
+        ByteSource::read_exact_at(&tf.fp, &mut buf, 5000).unwrap();
+        assert_eq!(buf, [0xee; 16]);
+
+        ByteSink::write_all_at(&tf.fp, &[0x11; 4], 0).unwrap();
+        let mut buf = [0u8; 4];
+        mmap.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, [0x11; 4]);
+    }
+
+    #[test]
+    fn sinks_are_shared_between_threads() {
+        let tf = TestFile::pattern(4000);
+        let mmap = AnonMMapMut::new(AddrHint::None, 4000, MMapConfig::new().map_private()).unwrap();
+        let mmap = SharedMMapMut::new(mmap);
+        let sinks: [&(dyn ByteSink + Sync); 2] = [&tf.fp, &mmap];
+        for sink in sinks {
+            std::thread::scope(|s| {
+                for i in 0..4u8 {
+                    s.spawn(move || sink.write_all_at(&[i; 1000], i as u64 * 1000).unwrap());
+                }
+            });
+            let mut buf = vec![0u8; 4000];
+            sink.read_exact_at(&mut buf, 0).unwrap();
+            assert!(buf.chunks(1000).enumerate().all(|(i, c)| c.iter().all(|b| *b == i as u8)));
+        }
+    }
+
+    #[test]
+    fn write_past_end_of_mapping() {
+        let mmap = AnonMMapMut::new(AddrHint::None, 10, MMapConfig::new().map_private()).unwrap();
+        let mmap = SharedMMapMut::new(mmap);
+        assert_eq!(mmap.write_at(&[1; 8], 6).unwrap(), 4);
+        let e = mmap.write_all_at(&[1; 8], 6).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+        assert_eq!(mmap.write_at(&[1], 10).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_at_mapped_bytes() {
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 10, MMapConfig::new().map_private()).unwrap();
+        mmap.copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let view = MappedBytes::new(crate::AnonMMap::try_from(mmap).unwrap()).slice(5..);
+        let mut buf = [0u8; 3];
+        view.read_exact_at(&mut buf, 1).unwrap();
+        assert_eq!(buf, [6, 7, 8]);
+    }
+}
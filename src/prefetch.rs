@@ -0,0 +1,414 @@
+use crate::{base, IncoreInfo, MAdviseConfig, MappedBytes};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// Number of pages prefetched between two checks for cancellation and budget.
+const CHUNK_PAGES: usize = 64;
+
+const PENDING: u8 = 0;
+const FINISHED: u8 = 1;
+const CANCELLED: u8 = 2;
+
+/// Prefetches ranges of mappings from a background thread, so that the
+/// threads accessing them later do not take major page faults.
+///
+/// Pages that are already resident (as reported by mincore) are skipped,
+/// the others are advised with MADV_WILLNEED and populated. Requests that
+/// overlap a pending request for the same mapping are merged into it.
+///
+/// The memory budget limits the number of bytes the prefetcher faults in
+/// for requests whose handles are still alive. Once the budget is used up
+/// the prefetcher waits until handles are dropped. A request that does not
+/// fit into the budget by itself is prefetched up to the budget and then
+/// cancelled.
+pub struct Prefetcher {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    used: usize,
+    budget: usize,
+    shutdown: bool,
+}
+
+/// Contiguous range of one mapping that is prefetched for one or more
+/// requests.
+struct Job {
+    data: MappedBytes,
+    next: usize,
+    requests: Vec<Arc<Request>>,
+    charge: Arc<Charge>,
+}
+
+struct Request {
+    range: Range<usize>,
+    done: AtomicUsize,
+    status: AtomicU8,
+    charge: Arc<Charge>,
+}
+
+/// Bytes faulted in for the requests of a job, released once the last of
+/// their handles is dropped. Only modified while holding the state lock.
+struct Charge {
+    bytes: AtomicUsize,
+    handles: AtomicUsize,
+}
+
+/// Progress and cancellation of a prefetch request.
+pub struct PrefetchHandle {
+    shared: Arc<Shared>,
+    request: Arc<Request>,
+}
+
+impl Prefetcher {
+    /// Start the background thread. At most `budget` bytes are faulted in
+    /// for requests whose handles are alive.
+    pub fn new(budget: usize) -> std::io::Result<Self> {
+        let state = State { jobs: VecDeque::new(), used: 0, budget, shutdown: false };
+        let shared = Arc::new(Shared { state: Mutex::new(state), cond: Condvar::new() });
+        let worker = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(String::from("mmap-prefetch"))
+                .spawn(move || shared.run())?
+        };
+        Ok(Self { shared, worker: Some(worker) })
+    }
+
+    /// Queue `range` of `data` for prefetching.
+    pub fn prefetch(&self, data: &MappedBytes, range: Range<usize>) -> PrefetchHandle {
+        assert!(range.start <= range.end && range.end <= data.len(), "range is out of the view");
+        let base = data.mapping_range().start;
+        let range = base + range.start..base + range.end;
+        let mut state = self.shared.lock();
+        let request = state.submit(data, range);
+        if request.range.is_empty() {
+            request.status.store(FINISHED, Ordering::Release);
+        }
+        self.shared.cond.notify_all();
+        PrefetchHandle { shared: self.shared.clone(), request }
+    }
+
+    /// Return the number of bytes currently charged against the budget.
+    pub fn used(&self) -> usize {
+        self.shared.lock().used
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        {
+            let mut state = self.shared.lock();
+            state.shutdown = true;
+            for job in state.jobs.drain(..) {
+                for r in job.requests {
+                    let _ = r.status.compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire);
+                }
+            }
+            self.shared.cond.notify_all();
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl PrefetchHandle {
+    /// Return the number of bytes of the request.
+    pub fn total(&self) -> usize {
+        self.request.range.len()
+    }
+
+    /// Return the number of bytes that have been prefetched so far.
+    pub fn done(&self) -> usize {
+        self.request.done.load(Ordering::Acquire)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.request.status.load(Ordering::Acquire) == FINISHED
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.request.status.load(Ordering::Acquire) == CANCELLED
+    }
+
+    /// Stop prefetching for this request. Pages that have been prefetched
+    /// stay charged against the budget until the handle is dropped.
+    pub fn cancel(&self) {
+        let _state = self.shared.lock();
+        let _ = self.request.status.compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire);
+        self.shared.cond.notify_all();
+    }
+
+    /// Block until the request is finished or cancelled.
+    pub fn wait(&self) {
+        let mut state = self.shared.lock();
+        while self.request.status.load(Ordering::Acquire) == PENDING {
+            state = self.shared.cond.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Drop for PrefetchHandle {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        let _ = self.request.status.compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire);
+        let charge = &self.request.charge;
+        if charge.handles.fetch_sub(1, Ordering::AcqRel) == 1 {
+            state.used -= charge.bytes.swap(0, Ordering::AcqRel);
+        }
+        self.shared.cond.notify_all();
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // the state stays consistent even if a thread panicked with the lock.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self) {
+        let ps = base::get_page_size() as usize;
+        let mut state = self.lock();
+        loop {
+            if state.shutdown {
+                return;
+            }
+            let Some((data, chunk, requests, charge)) = state.next_chunk(ps) else {
+                // requests may have been cancelled for lack of budget.
+                self.cond.notify_all();
+                state = self.cond.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            };
+            drop(state);
+            let faulted = touch(&data, ps);
+            state = self.lock();
+            for r in requests.iter() {
+                let overlap = chunk.end.min(r.range.end).saturating_sub(chunk.start.max(r.range.start));
+                r.done.fetch_add(overlap, Ordering::AcqRel);
+            }
+            if charge.handles.load(Ordering::Acquire) > 0 {
+                charge.bytes.fetch_add(faulted, Ordering::AcqRel);
+                state.used += faulted;
+            }
+            state.finish_jobs();
+            self.cond.notify_all();
+        }
+    }
+}
+
+impl State {
+    /// Add a request, merging it into a pending job for an overlapping or
+    /// adjacent range of the same mapping.
+    fn submit(&mut self, data: &MappedBytes, range: Range<usize>) -> Arc<Request> {
+        for job in self.jobs.iter_mut() {
+            let end = job.data.mapping_range().end;
+            if !job.data.same_mapping(data) || range.start > end || range.end < job.next {
+                continue;
+            }
+            job.next = job.next.min(range.start);
+            job.data = job.data.with_mapping_range(job.next..end.max(range.end));
+            job.charge.handles.fetch_add(1, Ordering::AcqRel);
+            let request = Arc::new(Request::new(range, job.charge.clone()));
+            job.requests.push(request.clone());
+            return request;
+        }
+        let charge = Arc::new(Charge { bytes: AtomicUsize::new(0), handles: AtomicUsize::new(1) });
+        let request = Arc::new(Request::new(range.clone(), charge.clone()));
+        let job = Job {
+            data: data.with_mapping_range(range.clone()),
+            next: range.start,
+            requests: vec![request.clone()],
+            charge,
+        };
+        self.jobs.push_back(job);
+        request
+    }
+
+    /// Pick the next chunk to prefetch, if any and if the budget allows.
+    /// The chunk is clamped so that faulting in all of its pages stays
+    /// within the budget.
+    #[allow(clippy::type_complexity)]
+    fn next_chunk(&mut self, ps: usize) -> Option<(MappedBytes, Range<usize>, Vec<Arc<Request>>, Arc<Charge>)> {
+        self.finish_jobs();
+        let used = self.used;
+        let room = self.budget.saturating_sub(used) / ps * ps;
+        let job = self.jobs.front_mut()?;
+        let end = job.data.mapping_range().end;
+        // the mapping starts on a page boundary, so do offsets in it.
+        let first_page = job.next - job.next % ps;
+        let chunk = job.next..(job.next + CHUNK_PAGES * ps).min(end).min(first_page.saturating_add(room));
+        if chunk.is_empty() {
+            if used == job.charge.bytes.load(Ordering::Acquire) {
+                // only the handles of this job hold the budget, waiting
+                // for them to be dropped would never end.
+                for r in job.requests.iter() {
+                    let _ = r.status.compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire);
+                }
+                self.jobs.pop_front();
+                return self.next_chunk(ps);
+            }
+            return None;
+        }
+        job.next = chunk.end;
+        let data = job.data.with_mapping_range(chunk.clone());
+        Some((data, chunk, job.requests.clone(), job.charge.clone()))
+    }
+
+    /// Drop cancelled requests and complete jobs without work left.
+    fn finish_jobs(&mut self) {
+        while let Some(job) = self.jobs.front_mut() {
+            job.requests.retain(|r| r.status.load(Ordering::Acquire) == PENDING);
+            if !job.requests.is_empty() && job.next < job.data.mapping_range().end {
+                return;
+            }
+            for r in job.requests.iter() {
+                r.status.store(FINISHED, Ordering::Release);
+            }
+            self.jobs.pop_front();
+        }
+    }
+}
+
+impl Request {
+    fn new(range: Range<usize>, charge: Arc<Charge>) -> Self {
+        Self { range, done: AtomicUsize::new(0), status: AtomicU8::new(PENDING), charge }
+    }
+}
+
+/// Fault in the pages of `data` which are not resident yet, returns the
+/// number of bytes faulted in.
+fn touch(data: &MappedBytes, ps: usize) -> usize {
+    if data.is_empty() {
+        return 0;
+    }
+    let Ok((addr, len)) = base::page_range(data.as_ptr(), data.len(), 0..data.len()) else {
+        return 0;
+    };
+    let _ = base::madvise_region(addr, len, MAdviseConfig::new().madv_willneed().value());
+    let faulted = match IncoreInfo::read_region(addr, len) {
+        Ok(info) => (len.div_ceil(ps) - info.resident_count()) * ps,
+        Err(_) => len,
+    };
+    if faulted > 0 {
+        // the first page may start before the view but still is part of
+        // the mapping. Whether the pages are backed by a file is unknown,
+        // so on kernels without MADV_POPULATE they are not touched at all.
+        // Errors are ignored, prefetching is a hint.
+        let _ = base::populate_region(addr, len, false, || Ok(base::Extent::Unknown));
+    }
+    faulted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddrHint, AnonMMap, MMapConfig};
+
+    fn page_size() -> usize {
+        base::get_page_size() as usize
+    }
+
+    fn fresh(pages: usize) -> MappedBytes {
+        let mmap = AnonMMap::new(AddrHint::None, pages * page_size(), MMapConfig::new().map_private()).unwrap();
+        MappedBytes::new(mmap)
+    }
+
+    #[test]
+    fn prefetch_makes_pages_resident() {
+        let ps = page_size();
+        let data = fresh(200);
+        let pf = Prefetcher::new(usize::MAX).unwrap();
+        let h = pf.prefetch(&data, 10 * ps..150 * ps);
+        h.wait();
+        assert!(h.is_finished());
+        assert_eq!(h.done(), h.total());
+        assert_eq!(h.total(), 140 * ps);
+        let mmap = data.with_mapping_range(0..200 * ps);
+        let (addr, len) = base::page_range(mmap.as_ptr(), mmap.len(), 0..mmap.len()).unwrap();
+        let info = IncoreInfo::read_region(addr, len).unwrap();
+        assert!((10..150).all(|p| info.is_resident(p)));
+    }
+
+    #[test]
+    fn overlapping_requests_are_merged() {
+        let ps = page_size();
+        let data = fresh(100);
+        let other = fresh(100);
+        let mut state = State { jobs: VecDeque::new(), used: 0, budget: 0, shutdown: false };
+        state.submit(&data, 0..10 * ps);
+        state.submit(&data, 5 * ps..20 * ps);
+        state.submit(&data, 20 * ps..30 * ps);
+        state.submit(&data, 50 * ps..60 * ps);
+        state.submit(&other, 0..10 * ps);
+        assert_eq!(state.jobs.len(), 3);
+        assert_eq!(state.jobs[0].data.mapping_range(), 0..30 * ps);
+        assert_eq!(state.jobs[0].requests.len(), 3);
+        assert_eq!(state.jobs[0].charge.handles.load(Ordering::Acquire), 3);
+    }
+
+    #[test]
+    fn budget_blocks_until_handles_are_dropped() {
+        let ps = page_size();
+        let pf = Prefetcher::new(4 * ps).unwrap();
+        let a = fresh(16);
+        let b = fresh(16);
+        let ha = pf.prefetch(&a, 0..2 * ps);
+        ha.wait();
+        assert!(ha.is_finished());
+        assert_eq!(pf.used(), 2 * ps);
+
+        // the request does not fit into the rest of the budget by itself.
+        let hb = pf.prefetch(&b, ps / 2..16 * ps);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(pf.used() <= 4 * ps);
+        assert!(!hb.is_finished());
+        assert_eq!(hb.done(), ps / 2 + ps);
+
+        // neither is the next one, it waits until the budget is free.
+        let hc = pf.prefetch(&a, 8 * ps..16 * ps);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(hc.done(), 0);
+
+        drop(ha);
+        hb.wait();
+        assert!(hb.is_cancelled());
+        assert_eq!(hb.done(), ps / 2 + 3 * ps);
+        assert_eq!(pf.used(), 4 * ps);
+        drop(hb);
+        hc.wait();
+        assert!(hc.is_cancelled());
+        assert_eq!(hc.done(), 4 * ps);
+        assert!(pf.used() <= 4 * ps);
+        drop(hc);
+        assert_eq!(pf.used(), 0);
+    }
+
+    #[test]
+    fn cancel_pending_request() {
+        let ps = page_size();
+        let pf = Prefetcher::new(ps).unwrap();
+        let a = fresh(4);
+        let ha = pf.prefetch(&a, 0..4 * ps);
+        ha.wait();
+        let hb = pf.prefetch(&a.slice(..), 0..ps);
+        let hc = pf.prefetch(&fresh(4), 0..4 * ps);
+        hc.cancel();
+        hc.wait();
+        assert!(hc.is_cancelled());
+        assert!(!hc.is_finished());
+        drop(ha);
+        hb.wait();
+        assert!(hb.is_finished());
+    }
+}
@@ -0,0 +1,161 @@
+use memchr::memmem;
+
+/// Divide `data` into at most `n` chunks of roughly equal size. Every chunk
+/// but the last one ends with `delim`, i.e. the boundaries are moved forward
+/// to the next delimiter so no record is split between two chunks. Fewer
+/// than `n` chunks are returned if records are longer than the chunk size.
+pub fn split_records(data: &[u8], n: usize, delim: u8) -> Vec<&[u8]> {
+    // every chunk holds at least one byte.
+    let mut chunks = Vec::with_capacity(n.min(data.len()));
+    if data.is_empty() || n == 0 {
+        return chunks;
+    }
+    let target = data.len().div_ceil(n);
+    let mut begin = 0;
+    while begin < data.len() {
+        let guess = std::cmp::min(begin + target, data.len());
+        // the chunk ends right after the first delimiter at or behind the
+        // guessed boundary, which starts the search one byte before it.
+        let end = match memchr::memchr(delim, &data[guess - 1..]) {
+            Some(i) => guess + i,
+            None => data.len(),
+        };
+        chunks.push(&data[begin..end]);
+        begin = end;
+    }
+    chunks
+}
+
+/// Iterator over the lines of a mapped region, see `FileMMap::lines`.
+/// Lines are split on `\n`, the line ending (`\n` or `\r\n`) is not part of
+/// the yielded slice.
+#[derive(Clone)]
+pub struct Lines<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Lines<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { rest: data }
+    }
+
+    /// Yield the lines as `&str`. Each line is only validated once it is
+    /// yielded, invalid lines are reported without stopping the iteration.
+    pub fn utf8(self) -> Utf8Lines<'a> {
+        Utf8Lines { inner: self }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (line, rest) = match memchr::memchr(b'\n', self.rest) {
+            Some(i) => (&self.rest[..i], &self.rest[i + 1..]),
+            None => (self.rest, &self.rest[self.rest.len()..]),
+        };
+        self.rest = rest;
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+}
+
+/// Iterator over the lines of a mapped region as `&str`, see `Lines::utf8`.
+#[derive(Clone)]
+pub struct Utf8Lines<'a> {
+    inner: Lines<'a>,
+}
+
+impl<'a> Iterator for Utf8Lines<'a> {
+    type Item = Result<&'a str, std::str::Utf8Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(std::str::from_utf8)
+    }
+}
+
+/// Iterator over the offsets of all non-overlapping occurrences of a
+/// pattern, see `FileMMap::find_all`.
+pub struct FindAll<'h, 'n> {
+    inner: memmem::FindIter<'h, 'n>,
+}
+
+impl<'h, 'n> FindAll<'h, 'n> {
+    pub(crate) fn new(haystack: &'h [u8], needle: &'n [u8]) -> Self {
+        Self { inner: memmem::find_iter(haystack, needle) }
+    }
+}
+
+impl Iterator for FindAll<'_, '_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_on_newlines() {
+        let data = b"aaaa\nbb\ncccccc\nd\neeeeeeeeee\nf";
+        let chunks = split_records(data, 3, b'\n');
+        assert!(chunks.len() <= 3);
+        assert_eq!(chunks.concat(), data.to_vec());
+        for c in &chunks[..chunks.len() - 1] {
+            assert_eq!(*c.last().unwrap(), b'\n');
+        }
+    }
+
+    #[test]
+    fn split_record_longer_than_chunk() {
+        let data = b"aaaaaaaaaaaaaaaaaaaa\nb\n";
+        let chunks = split_records(data, 4, b'\n');
+        assert_eq!(chunks, vec![&data[..21], &data[21..]]);
+    }
+
+    #[test]
+    fn split_into_more_chunks_than_bytes() {
+        let data = b"a\nb\n";
+        assert_eq!(split_records(data, usize::MAX, b'\n'), vec![&b"a\n"[..], &b"b\n"[..]]);
+        assert!(split_records(b"", usize::MAX, b'\n').is_empty());
+    }
+
+    #[test]
+    fn split_boundary_on_delimiter() {
+        let data = b"ab\ncd\n";
+        assert_eq!(split_records(data, 2, b'\n'), vec![&b"ab\n"[..], &b"cd\n"[..]]);
+        assert_eq!(split_records(data, 1, b'\n'), vec![&data[..]]);
+        assert!(split_records(b"", 4, b'\n').is_empty());
+        assert!(split_records(data, 0, b'\n').is_empty());
+    }
+
+    #[test]
+    fn lines_strip_line_endings() {
+        let data = b"one\r\ntwo\n\nthree";
+        let lines = Lines::new(data).collect::<Vec<_>>();
+        assert_eq!(lines, vec![&b"one"[..], b"two", b"", b"three"]);
+        assert_eq!(Lines::new(b"x\n").count(), 1);
+    }
+
+    #[test]
+    fn utf8_lines_are_validated_lazily() {
+        let data = b"ok\n\xff\xfe\nfine";
+        let mut lines = Lines::new(data).utf8();
+        assert_eq!(lines.next().unwrap().unwrap(), "ok");
+        assert!(lines.next().unwrap().is_err());
+        assert_eq!(lines.next().unwrap().unwrap(), "fine");
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn find_all_offsets() {
+        let data = b"abcabcab";
+        assert_eq!(FindAll::new(data, b"ab").collect::<Vec<_>>(), vec![0, 3, 6]);
+        assert_eq!(FindAll::new(data, b"xyz").count(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{MMapMut, MSyncType, MAdviseConfig};
+use std::sync::atomic::{AtomicU8, AtomicU32, AtomicU64};
+
+/// Writable mapping that can be shared between threads.
+///
+/// The wrapped mapping is only accessible through atomic views, i.e. every
+/// access to the mapped memory goes through `AtomicU8`, `AtomicU32` or
+/// `AtomicU64`. This makes concurrent writes from several threads well
+/// defined, without handing out aliasing `&mut [u8]` slices.
+///
+/// Use `into_inner` to get the plain mapping back once all threads are done.
+pub struct SharedMMapMut<M: MMapMut> {
+    inner: M,
+}
+
+// SAFETY: the inner mapping is never exposed by reference, all accesses to
+// the mapped memory go through atomics which are safe to use concurrently.
+unsafe impl<M: MMapMut + Send> Sync for SharedMMapMut<M> {}
+
+impl<M: MMapMut> SharedMMapMut<M> {
+    pub fn new(mmap: M) -> Self {
+        Self { inner: mmap }
+    }
+
+    /// Return length of the mapped region.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// View the mapped region as a slice of bytes which can be read and
+    /// written concurrently.
+    pub fn as_atomic_slice(&self) -> &[AtomicU8] {
+        // AtomicU8 has the same size and alignment as u8.
+        unsafe {
+            std::slice::from_raw_parts(self.inner.as_ptr() as *const AtomicU8, self.inner.len())
+        }
+    }
+
+    /// View the four bytes at `off` as an atomic integer. Returns None if
+    /// `off` is not aligned or the integer would not fit into the mapping.
+    pub fn atomic_u32(&self, off: usize) -> Option<&AtomicU32> {
+        self.atomic_at::<AtomicU32>(off)
+    }
+
+    /// View the eight bytes at `off` as an atomic integer. Returns None if
+    /// `off` is not aligned or the integer would not fit into the mapping.
+    pub fn atomic_u64(&self, off: usize) -> Option<&AtomicU64> {
+        self.atomic_at::<AtomicU64>(off)
+    }
+
+    fn atomic_at<T>(&self, off: usize) -> Option<&T> {
+        let size = std::mem::size_of::<T>();
+        if off.checked_add(size)? > self.inner.len() {
+            return None;
+        }
+        let ptr = self.inner.as_ptr().wrapping_add(off);
+        if !(ptr as usize).is_multiple_of(std::mem::align_of::<T>()) {
+            return None;
+        }
+        unsafe { Some(&*(ptr as *const T)) }
+    }
+
+    pub fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
+        self.inner.sync(typ)
+    }
+
+    pub fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
+        self.inner.advise(config)
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnonMMapMut, AddrHint, MMapConfig};
+    use std::sync::atomic::Ordering;
+
+    fn setup(len: usize) -> SharedMMapMut<AnonMMapMut> {
+        let mmap = AnonMMapMut::new(AddrHint::None, len, MMapConfig::new().map_shared()).unwrap();
+        SharedMMapMut::new(mmap)
+    }
+
+    #[test]
+    fn concurrent_counter() {
+        let shared = setup(4096);
+        let nthreads = 8;
+        std::thread::scope(|s| {
+            for _ in 0..nthreads {
+                s.spawn(|| {
+                    let cnt = shared.atomic_u64(64).unwrap();
+                    for _ in 0..1000 {
+                        cnt.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let mmap = shared.into_inner();
+        assert_eq!(u64::from_ne_bytes(mmap[64..72].try_into().unwrap()), nthreads * 1000);
+    }
+
+    #[test]
+    fn disjoint_writers() {
+        let shared = setup(8 * 4096);
+        std::thread::scope(|s| {
+            for (t, chunk) in shared.as_atomic_slice().chunks(4096).enumerate() {
+                s.spawn(move || {
+                    for b in chunk {
+                        b.store(t as u8, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let mmap = shared.into_inner();
+        for (t, chunk) in mmap.chunks(4096).enumerate() {
+            assert!(chunk.iter().all(|b| *b == t as u8));
+        }
+    }
+
+    #[test]
+    fn unaligned_or_out_of_bounds_atomics() {
+        let shared = setup(4096);
+        assert!(shared.atomic_u32(1).is_none());
+        assert!(shared.atomic_u64(4).is_none());
+        assert!(shared.atomic_u64(4096 - 8).is_some());
+        assert!(shared.atomic_u64(4096).is_none());
+        assert!(shared.atomic_u32(usize::MAX).is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{base, MSyncType, MAdviseConfig};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+/// Writable view of a page aligned part of a mapping. Views created by
+/// `MMapMut::split_at_page_mut` or `MMapMut::chunks_mut_pages` never overlap,
+/// so each of them can be handed to a different thread.
+pub struct MMapSliceMut<'a> {
+    ptr: *mut u8,
+    len: usize,
+    /// Part of a shared file mapping, see `MMap::is_shared_file`.
+    shared_file: bool,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+// SAFETY: a view is an exclusive borrow of its part of the mapping, just
+// like a `&mut [u8]`.
+unsafe impl Send for MMapSliceMut<'_> {}
+unsafe impl Sync for MMapSliceMut<'_> {}
+
+impl<'a> MMapSliceMut<'a> {
+    /// The caller must ensure that the region is page aligned, mapped
+    /// writable and not accessible through any other reference for 'a.
+    pub(crate) unsafe fn new(ptr: *mut u8, len: usize, shared_file: bool) -> Self {
+        Self { ptr, len, shared_file, _marker: PhantomData }
+    }
+
+    /// Flush the modified pages of this view only.
+    pub fn sync(&self, typ: MSyncType) -> std::io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        base::msync_region(self.ptr, self.len, &typ)
+    }
+
+    /// Give advice about the access pattern of this view only, see 
+    /// `MMap::advise`.
+    pub fn advise(&self, config: MAdviseConfig) -> std::io::Result<()> {
+        base::check_advice(&config, self.shared_file)?;
+        if self.len == 0 {
+            return Ok(());
+        }
+        base::madvise_region(self.ptr, self.len, config.value())
+    }
+
+    /// Split the view further, `mid` must be page aligned.
+    pub fn split_at_page_mut(self, mid: usize) -> std::io::Result<(Self, Self)> {
+        let ps = base::get_page_size() as usize;
+        if mid > self.len || (mid != self.len && !mid.is_multiple_of(ps)) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "split point is not page aligned"));
+        }
+        unsafe {
+            Ok((Self::new(self.ptr, mid, self.shared_file), Self::new(self.ptr.add(mid), self.len - mid, self.shared_file)))
+        }
+    }
+}
+
+impl Deref for MMapSliceMut<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for MMapSliceMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+/// Iterator over disjoint, page aligned writable views of a mapping.
+pub struct ChunksMutPages<'a> {
+    ptr: *mut u8,
+    len: usize,
+    chunk: usize,
+    shared_file: bool,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+// SAFETY: the iterator only hands out views of the region it borrows.
+unsafe impl Send for ChunksMutPages<'_> {}
+
+impl ChunksMutPages<'_> {
+    pub(crate) fn new(ptr: *mut u8, len: usize, chunk: usize, shared_file: bool) -> Self {
+        Self { ptr, len, chunk, shared_file, _marker: PhantomData }
+    }
+}
+
+impl<'a> Iterator for ChunksMutPages<'a> {
+    type Item = MMapSliceMut<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let n = std::cmp::min(self.chunk, self.len);
+        let view = unsafe { MMapSliceMut::new(self.ptr, n, self.shared_file) };
+        self.ptr = self.ptr.wrapping_add(n);
+        self.len -= n;
+        Some(view)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.len.div_ceil(self.chunk);
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for ChunksMutPages<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::io::Read;
+
+    fn page_size() -> usize {
+        crate::base::get_page_size() as usize
+    }
+
+    #[test]
+    fn split_at_page() {
+        let ps = page_size();
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 4 * ps, MMapConfig::new().map_private()).unwrap();
+        let (mut a, mut b) = mmap.split_at_page_mut(ps).unwrap();
+        assert_eq!(a.len(), ps);
+        assert_eq!(b.len(), 3 * ps);
+        a.fill(1);
+        b.fill(2);
+        assert!(mmap[..ps].iter().all(|x| *x == 1));
+        assert!(mmap[ps..].iter().all(|x| *x == 2));
+    }
+
+    #[test]
+    fn split_at_unaligned_offset_fails() {
+        let ps = page_size();
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 4 * ps, MMapConfig::new().map_private()).unwrap();
+        let e = mmap.split_at_page_mut(ps + 1).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(mmap.split_at_page_mut(5 * ps).is_err());
+        let (a, b) = mmap.split_at_page_mut(4 * ps).unwrap();
+        assert_eq!((a.len(), b.len()), (4 * ps, 0));
+    }
+
+    #[test]
+    fn chunks_cover_mapping() {
+        let ps = page_size();
+        let len = 10 * ps + 123;
+        let mut mmap = AnonMMapMut::new(AddrHint::None, len, MMapConfig::new().map_private()).unwrap();
+        let chunks = mmap.chunks_mut_pages(3);
+        assert_eq!(chunks.len(), 4);
+        let lens = chunks.map(|c| c.len()).collect::<Vec<_>>();
+        assert_eq!(lens, vec![3 * ps, 3 * ps, 3 * ps, ps + 123]);
+        let chunks = mmap.chunks_mut_pages(usize::MAX);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks.map(|c| c.len()).collect::<Vec<_>>(), vec![len]);
+    }
+
+    #[test]
+    fn parallel_writers_flush_own_chunk() {
+        let ps = page_size();
+        let mut tf = crate::testutil::TestFile::new(&vec![0u8; 8 * ps]);
+        let mut mmap = FileMMapMut::new(AddrHint::None, MMapConfig::new().map_shared(), &tf.fp, 0).unwrap();
+        std::thread::scope(|s| {
+            for (i, mut chunk) in mmap.chunks_mut_pages(2).enumerate() {
+                s.spawn(move || {
+                    chunk.fill(i as u8 + 1);
+                    chunk.sync(MSyncType::Sync).unwrap();
+                });
+            }
+        });
+        let mut buf = Vec::new();
+        tf.fp.read_to_end(&mut buf).unwrap();
+        for (i, chunk) in buf.chunks(2 * ps).enumerate() {
+            assert!(chunk.iter().all(|x| *x == i as u8 + 1));
+        }
+    }
+
+    /// Return the bounds and VmFlags of the area of /proc/self/smaps that
+    /// contains `addr`.
+    #[cfg(target_os = "linux")]
+    fn vm_area(addr: *const u8) -> Option<(usize, usize, String)> {
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let mut area = None;
+        for line in smaps.lines() {
+            if let Some(flags) = line.strip_prefix("VmFlags:") {
+                if let Some((start, end)) = area {
+                    return Some((start, end, flags.trim().to_string()));
+                }
+                continue;
+            }
+            let bounds = line.split_whitespace().next().and_then(|b| b.split_once('-'));
+            if let Some((start, end)) = bounds {
+                if let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+                    area = (start..end).contains(&(addr as usize)).then_some((start, end));
+                }
+            }
+        }
+        None
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn advice_reaches_the_kernel() {
+        let has = |flags: &str, f: &str| flags.split_whitespace().any(|x| x == f);
+        let ps = page_size();
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 4 * ps, MMapConfig::new().map_private()).unwrap();
+        let base = mmap.as_ptr() as usize;
+        // only the page overlapping the range is advised.
+        mmap.advise_range(ps + 10..2 * ps, MAdviseConfig::new().madv_sequential()).unwrap();
+        let (start, end, flags) = vm_area((base + ps) as *const u8).unwrap();
+        assert_eq!((start, end), (base + ps, base + 2 * ps));
+        assert!(has(&flags, "sr"), "{}", flags);
+        let (_, _, flags) = vm_area(base as *const u8).unwrap();
+        assert!(!has(&flags, "sr"), "{}", flags);
+
+        let (_, right) = mmap.split_at_page_mut(2 * ps).unwrap();
+        right.advise(MAdviseConfig::new().madv_random()).unwrap();
+        let (start, _, flags) = vm_area((base + 3 * ps) as *const u8).unwrap();
+        assert_eq!(start, base + 2 * ps);
+        assert!(has(&flags, "rr"), "{}", flags);
+    }
+
+    #[test]
+    fn sync_and_advise_range() {
+        let ps = page_size();
+        let mmap = AnonMMapMut::new(AddrHint::None, 4 * ps, MMapConfig::new().map_private()).unwrap();
+        mmap.sync_range(ps + 10..2 * ps, MSyncType::Async).unwrap();
+        mmap.advise_range(10..3 * ps, MAdviseConfig::new().madv_willneed()).unwrap();
+        assert!(mmap.sync_range(0..5 * ps, MSyncType::Async).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_chunks_mut_fill() {
+        use rayon::prelude::*;
+        let ps = page_size();
+        let mut mmap = AnonMMapMut::new(AddrHint::None, 16 * ps, MMapConfig::new().map_private()).unwrap();
+        mmap.par_chunks_mut(1).enumerate().for_each(|(i, mut c)| c.fill(i as u8));
+        let sums = mmap.par_chunks(1).map(|c| c[0] as usize).sum::<usize>();
+        assert_eq!(sums, (0..16).sum::<usize>());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{AddrHint, ByteSource, FileMMap, MMapConfig};
+use std::fs::File;
+use std::os::unix::prelude::AsRawFd;
+use std::path::Path;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::error::{last_errno, MMapError};
+
+/// Files smaller than this are read instead of mapped by default.
+const DEFAULT_MIN_MAP_LEN: u64 = 64 * 1024;
+
+/// How the contents of a file are accessed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessStrategy {
+    /// The file is memory mapped.
+    Map,
+    /// The file is read with pread.
+    Read,
+}
+
+/// Options for `open_best_with`.
+#[derive(Clone, Copy, Debug)]
+pub struct OpenConfig {
+    min_map_len: u64,
+    allow_remote: bool,
+    force: Option<AccessStrategy>,
+}
+
+impl Default for OpenConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenConfig {
+    /// Map files of at least 64 KiB on local file systems.
+    pub fn new() -> Self {
+        Self { min_map_len: DEFAULT_MIN_MAP_LEN, allow_remote: false, force: None }
+    }
+
+    /// Files smaller than `len` bytes are read instead of mapped.
+    pub fn min_map_len(mut self, len: u64) -> Self {
+        self.min_map_len = len;
+        self
+    }
+
+    /// Also map files on network and FUSE file systems. Accessing such a
+    /// mapping raises SIGBUS if the server fails to deliver a page.
+    pub fn allow_remote_fs(mut self, allow: bool) -> Self {
+        self.allow_remote = allow;
+        self
+    }
+
+    /// Skip all checks and always use `strategy`.
+    pub fn force(mut self, strategy: AccessStrategy) -> Self {
+        self.force = Some(strategy);
+        self
+    }
+}
+
+/// File opened by `open_best`, either mapped or read with pread. Both
+/// variants are accessed through `ByteSource`.
+pub enum BestSource {
+    Mapped(FileMMap),
+    Read(File),
+}
+
+impl BestSource {
+    pub fn strategy(&self) -> AccessStrategy {
+        match self {
+            Self::Mapped(_) => AccessStrategy::Map,
+            Self::Read(_) => AccessStrategy::Read,
+        }
+    }
+}
+
+impl ByteSource for BestSource {
+    fn size(&self) -> std::io::Result<u64> {
+        match self {
+            Self::Mapped(m) => m.size(),
+            Self::Read(f) => f.size(),
+        }
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        match self {
+            Self::Mapped(m) => m.read_at(buf, offset),
+            Self::Read(f) => f.read_at(buf, offset),
+        }
+    }
+}
+
+/// Open the file at `path` read only with the default `OpenConfig`.
+pub fn open_best<P: AsRef<Path>>(path: P) -> std::io::Result<BestSource> {
+    open_best_with(path, OpenConfig::new())
+}
+
+/// Open the file at `path` read only and map it, unless it is smaller than
+/// the configured threshold or lives on a network or FUSE file system, in
+/// which case it is read with pread.
+pub fn open_best_with<P: AsRef<Path>>(path: P, config: OpenConfig) -> std::io::Result<BestSource> {
+    let file = File::open(path)?;
+    let strategy = match config.force {
+        Some(s) => s,
+        None => choose(&file, &config)?,
+    };
+    match strategy {
+        AccessStrategy::Map => {
+            let mmap = FileMMap::new(AddrHint::None, MMapConfig::new().map_shared(), &file, 0)?;
+            Ok(BestSource::Mapped(mmap))
+        }
+        AccessStrategy::Read => Ok(BestSource::Read(file)),
+    }
+}
+
+fn choose(file: &File, config: &OpenConfig) -> std::io::Result<AccessStrategy> {
+    if file.metadata()?.len() < config.min_map_len {
+        return Ok(AccessStrategy::Read);
+    }
+    if !config.allow_remote && is_remote_fs(file)? {
+        return Ok(AccessStrategy::Read);
+    }
+    Ok(AccessStrategy::Map)
+}
+
+/// Check if the file lives on a network or FUSE file system.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_remote_fs(file: &File) -> std::io::Result<bool> {
+    const REMOTE_MAGIC: &[u32] = &[
+        0x6969,     // nfs
+        0x65735546, // fuse
+        0xff534d42, // cifs
+        0xfe534d42, // smb2
+        0x517b,     // smb
+        0x01021997, // 9p
+        0x00c36400, // ceph
+        0x5346414f, // afs
+        0x013111a8, // ibrix
+        0x0bd00bd0, // lustre
+    ];
+    let mut buf = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    let buf = unsafe {
+        if libc::fstatfs(file.as_raw_fd(), buf.as_mut_ptr()) != 0 {
+            return Err(MMapError::Fstatfs { errno: last_errno(), fd: file.as_raw_fd() }.into());
+        }
+        buf.assume_init()
+    };
+    Ok(REMOTE_MAGIC.contains(&(buf.f_type as u32)))
+}
+
+/// Check if the file lives on a network or FUSE file system.
+#[cfg(target_os = "macos")]
+pub(crate) fn is_remote_fs(file: &File) -> std::io::Result<bool> {
+    const REMOTE_NAMES: &[&str] = &["nfs", "smbfs", "afpfs", "webdav", "macfuse", "osxfuse"];
+    let mut buf = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    let buf = unsafe {
+        if libc::fstatfs(file.as_raw_fd(), buf.as_mut_ptr()) != 0 {
+            return Err(MMapError::Fstatfs { errno: last_errno(), fd: file.as_raw_fd() }.into());
+        }
+        buf.assume_init()
+    };
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.f_fstypename.as_ptr()) };
+    Ok(REMOTE_NAMES.iter().any(|n| name.to_bytes() == n.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn is_remote_fs(_file: &File) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestFile;
+
+    #[test]
+    fn small_files_are_read() {
+        let tf = TestFile::new(&[0x5a; 100]);
+        let src = open_best(&tf.path).unwrap();
+        assert_eq!(src.strategy(), AccessStrategy::Read);
+        let mut buf = [0u8; 10];
+        src.read_exact_at(&mut buf, 90).unwrap();
+        assert_eq!(buf, [0x5a; 10]);
+    }
+
+    #[test]
+    fn empty_files_are_read() {
+        let tf = TestFile::new(&[]);
+        let src = open_best(&tf.path).unwrap();
+        assert_eq!(src.strategy(), AccessStrategy::Read);
+        assert_eq!(src.size().unwrap(), 0);
+    }
+
+    #[test]
+    fn large_local_files_are_mapped() {
+        let tf = TestFile::new(&vec![0x5a; 200 * 1024]);
+        let remote = is_remote_fs(&File::open(&tf.path).unwrap()).unwrap();
+        let src = open_best(&tf.path).unwrap();
+        let expected = if remote { AccessStrategy::Read } else { AccessStrategy::Map };
+        assert_eq!(src.strategy(), expected);
+        assert_eq!(src.size().unwrap(), 200 * 1024);
+    }
+
+    #[test]
+    fn threshold_and_override() {
+        let tf = TestFile::new(&[0x5a; 1000]);
+        let conf = OpenConfig::new().min_map_len(0).allow_remote_fs(true);
+        assert_eq!(open_best_with(&tf.path, conf).unwrap().strategy(), AccessStrategy::Map);
+        let conf = OpenConfig::new().min_map_len(0).force(AccessStrategy::Read);
+        assert_eq!(open_best_with(&tf.path, conf).unwrap().strategy(), AccessStrategy::Read);
+        let conf = OpenConfig::new().force(AccessStrategy::Map);
+        let src = open_best_with(&tf.path, conf).unwrap();
+        assert_eq!(src.strategy(), AccessStrategy::Map);
+        let mut buf = [0u8; 4];
+        src.read_exact_at(&mut buf, 996).unwrap();
+        assert_eq!(buf, [0x5a; 4]);
+    }
+}
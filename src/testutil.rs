@@ -0,0 +1,64 @@
+//! Fixtures shared by the test modules.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Return a path in the temp directory that no other test of this or
+/// any other process uses. The file is not created.
+pub(crate) fn temp_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("mmap-test-{}-{}.txt", std::process::id(), n))
+}
+
+/// Return the bytes at `range` of a file created with `TestFile::pattern`.
+pub(crate) fn pattern(range: Range<usize>) -> Vec<u8> {
+    range.map(|i| (i % 251) as u8).collect()
+}
+
+/// File at a unique temp path which is removed on drop, together with the
+/// paths passed to `remove_also`.
+pub(crate) struct TestFile {
+    pub(crate) path: PathBuf,
+    /// The file opened for reading and writing.
+    pub(crate) fp: File,
+    others: Vec<PathBuf>,
+}
+
+impl TestFile {
+    /// Create a file holding `contents`.
+    pub(crate) fn new(contents: &[u8]) -> Self {
+        let path = temp_path();
+        let mut fp = OpenOptions::new().read(true).write(true).create_new(true).open(&path).unwrap();
+        fp.write_all(contents).unwrap();
+        Self { path, fp, others: Vec::new() }
+    }
+
+    /// Create a file of `len` bytes, see `pattern`.
+    pub(crate) fn pattern(len: usize) -> Self {
+        Self::new(&pattern(0..len))
+    }
+
+    /// Append `contents` through a separate file descriptor.
+    pub(crate) fn append(&self, contents: &[u8]) {
+        let mut fp = OpenOptions::new().append(true).open(&self.path).unwrap();
+        fp.write_all(contents).unwrap();
+    }
+
+    /// Remove `path` as well when the file is dropped.
+    pub(crate) fn remove_also(&mut self, path: PathBuf) {
+        self.others.push(path);
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        for p in self.others.iter() {
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
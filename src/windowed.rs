@@ -0,0 +1,213 @@
+use crate::{base, AddrHint, FileMMap, MMapConfig};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex};
+
+/// Mapping of a file that may be larger than the address space the process
+/// is allowed to use.
+///
+/// The file is mapped in page aligned windows of a fixed size on demand.
+/// At most `max_windows` windows are kept mapped, the least recently used
+/// one is unmapped when another one is needed. A range that straddles two
+/// windows is served from a window mapped just for that range, ranges
+/// larger than a window are rejected.
+///
+/// Note that a `WindowGuard` keeps its window mapped after it was evicted,
+/// so `max_windows` only limits the windows kept for reuse. As long as
+/// guards are alive, more windows than that may be mapped.
+pub struct WindowedFileMap {
+    file: File,
+    file_len: u64,
+    window_len: usize,
+    max_windows: usize,
+    conf: MMapConfig,
+    windows: Mutex<VecDeque<Window>>,
+}
+
+/// Mapped window, `mmap` starts at `off` in the file.
+struct Window {
+    off: u64,
+    mmap: Arc<FileMMap>,
+}
+
+/// Slice of a `WindowedFileMap` returned by `get`. The window stays mapped
+/// as long as the guard is alive, even if it was evicted in the meantime.
+pub struct WindowGuard {
+    mmap: Option<Arc<FileMMap>>,
+    off: usize,
+    len: usize,
+}
+
+impl WindowedFileMap {
+    /// Map `file` read only in windows of `window_len` bytes (rounded up to
+    /// the page size), keeping at most `max_windows` of them mapped.
+    pub fn new(file: File, window_len: usize, max_windows: usize) -> std::io::Result<Self> {
+        Self::with_config(file, window_len, max_windows, MMapConfig::new().map_shared())
+    }
+
+    /// Like `new` but the windows are mapped with `conf`.
+    pub fn with_config(file: File, window_len: usize, max_windows: usize, conf: MMapConfig) -> std::io::Result<Self> {
+        if window_len == 0 || max_windows == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "window size and count must be non-zero"));
+        }
+        let ps = base::get_page_size() as usize;
+        let window_len = window_len.div_ceil(ps) * ps;
+        let file_len = file.metadata()?.len();
+        let windows = Mutex::new(VecDeque::with_capacity(max_windows));
+        Ok(Self { file, file_len, window_len, max_windows, conf, windows })
+    }
+
+    /// Return the length of the file when it was opened.
+    pub fn len(&self) -> u64 {
+        self.file_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_len == 0
+    }
+
+    /// Return the size of the windows.
+    pub fn window_len(&self) -> usize {
+        self.window_len
+    }
+
+    /// Return the number of windows that are currently mapped.
+    pub fn mapped_windows(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Return the bytes at `range` of the file, mapping a window containing
+    /// them if necessary. Fails with InvalidInput if the range is larger
+    /// than a window.
+    pub fn get(&self, range: Range<u64>) -> std::io::Result<WindowGuard> {
+        if range.start > range.end || range.end > self.file_len {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of the file"));
+        }
+        if range.end - range.start > self.window_len as u64 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is larger than a window"));
+        }
+        if range.is_empty() {
+            return Ok(WindowGuard { mmap: None, off: 0, len: 0 });
+        }
+        let mut windows = self.lock();
+        let hit = windows.iter().position(|w| w.off <= range.start && range.end <= w.off + w.mmap.len() as u64);
+        let window = match hit {
+            Some(i) => {
+                let w = windows.remove(i).unwrap();
+                windows.push_front(w);
+                &windows[0]
+            }
+            None => {
+                let w = self.map_window(&range)?;
+                if windows.len() == self.max_windows {
+                    windows.pop_back();
+                }
+                windows.push_front(w);
+                &windows[0]
+            }
+        };
+        let off = (range.start - window.off) as usize;
+        let len = (range.end - range.start) as usize;
+        Ok(WindowGuard { mmap: Some(window.mmap.clone()), off, len })
+    }
+
+    /// Unmap all windows which are not referenced by a guard.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Map the window containing `range`. If the range crosses a window
+    /// boundary, the covering range of pages is mapped instead, which is
+    /// at most one page larger than a window.
+    fn map_window(&self, range: &Range<u64>) -> std::io::Result<Window> {
+        let wlen = self.window_len as u64;
+        let mut off = range.start - range.start % wlen;
+        let mut end = std::cmp::min(off + wlen, self.file_len);
+        if range.end > end {
+            let ps = base::get_page_size() as u64;
+            off = range.start - range.start % ps;
+            end = range.end;
+        }
+        let mmap = FileMMap::new_range(AddrHint::None, self.conf, &self.file, off as i64, (end - off) as usize)?;
+        Ok(Window { off, mmap: Arc::new(mmap) })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Window>> {
+        self.windows.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Deref for WindowGuard {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        match &self.mmap {
+            Some(mmap) => &mmap[self.off..self.off + self.len],
+            None => &[],
+        }
+    }
+}
+
+impl AsRef<[u8]> for WindowGuard {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{pattern, TestFile};
+
+    #[test]
+    fn windows_are_mapped_on_demand() {
+        let ps = base::get_page_size() as usize;
+        let len = 10 * ps + 123;
+        let tf = TestFile::pattern(len);
+        let map = WindowedFileMap::new(File::open(&tf.path).unwrap(), 2 * ps, 2).unwrap();
+        assert_eq!(map.len(), len as u64);
+        assert_eq!(map.mapped_windows(), 0);
+
+        let a = map.get(10..100).unwrap();
+        assert_eq!(a[..], pattern(10..100)[..]);
+        let b = map.get(len as u64 - 100..len as u64).unwrap();
+        assert_eq!(b[..], pattern(len - 100..len)[..]);
+        assert_eq!(map.mapped_windows(), 2);
+
+        // the third window evicts the first one, the guard stays valid.
+        let c = map.get(4 * ps as u64..5 * ps as u64).unwrap();
+        assert_eq!(c[..], pattern(4 * ps..5 * ps)[..]);
+        assert_eq!(map.mapped_windows(), 2);
+        assert_eq!(a[..], pattern(10..100)[..]);
+
+        // served from a cached window.
+        let d = map.get(4 * ps as u64 + 1..6 * ps as u64).unwrap();
+        assert_eq!(d[..], pattern(4 * ps + 1..6 * ps)[..]);
+        assert_eq!(map.mapped_windows(), 2);
+    }
+
+    #[test]
+    fn straddling_ranges() {
+        let ps = base::get_page_size() as usize;
+        let len = 8 * ps;
+        let tf = TestFile::pattern(len);
+        let map = WindowedFileMap::new(File::open(&tf.path).unwrap(), 4 * ps, 4).unwrap();
+        let r = 2 * ps as u64 - 10..5 * ps as u64 + 10;
+        let g = map.get(r.clone()).unwrap();
+        assert_eq!(g[..], pattern(r.start as usize..r.end as usize)[..]);
+        assert!(map.get(0..0).unwrap().is_empty());
+        let e = map.get(0..len as u64 + 1).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        // a range larger than a window is not mapped.
+        let e = map.get(ps as u64..5 * ps as u64 + 1).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(map.mapped_windows(), 1);
+        map.clear();
+        assert_eq!(map.mapped_windows(), 0);
+        assert_eq!(g[..10], pattern(r.start as usize..r.start as usize + 10)[..]);
+    }
+}